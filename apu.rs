@@ -0,0 +1,127 @@
+// Clock cycles between frame sequencer steps, it runs at 512 Hz off the normal speed clock
+pub const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+
+// Index into regs of NR14, NR24, NR34 and NR44, whose bit 6 enables the length counter of each channel
+const LENGTH_ENABLE: [usize; 4] = [0x04, 0x09, 0x0E, 0x13];
+
+// Bits that always read as 1 in 0xFF10-0xFF2F, unused registers read as 0xFF
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // unused
+];
+
+// Audio processing unit registers (0xFF10-0xFF3F)
+pub struct Apu {
+    // 0xFF10-0xFF2F as last written
+    regs: [u8; 0x20],
+    // 0xFF30-0xFF3F: wave pattern RAM
+    wave_ram: [u8; 0x10],
+    // NR52 bit 7, all registers are cleared and read-only while the APU is off
+    enabled: bool,
+    // NR52 bits 0-3, set when a channel is triggered with its DAC on
+    channels: u8,
+    // Length counter of each channel, the channel is switched off when an enabled counter runs out
+    lengths: [u16; 4],
+    // Next frame sequencer step, 0-7
+    frame_step: u8,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu { regs: [0; 0x20], wave_ram: [0; 0x10], enabled: false, channels: 0, lengths: [0; 4], frame_step: 0 }
+    }
+
+    // Read an APU register, write-only and unused bits read as 1
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF26 => READ_MASKS[0x16] | (self.enabled as u8) << 7 | self.channels,
+            0xFF10..=0xFF2F => READ_MASKS[addr as usize - 0xFF10] | self.regs[addr as usize - 0xFF10],
+            0xFF30..=0xFF3F => self.wave_ram[addr as usize - 0xFF30],
+            _ => 0xFF,
+        }
+    }
+
+    // Write an APU register
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF26 => {
+                let enabled = val & 0b1000_0000 != 0;
+                if !enabled {
+                    // Powering off clears every register
+                    self.regs = [0; 0x20];
+                    self.channels = 0;
+                } else if !self.enabled {
+                    // Powering on restarts the frame sequencer
+                    self.frame_step = 0;
+                }
+                self.enabled = enabled;
+            },
+            0xFF30..=0xFF3F => self.wave_ram[addr as usize - 0xFF30] = val,
+            0xFF10..=0xFF25 if self.enabled => {
+                self.regs[addr as usize - 0xFF10] = val;
+                self.load_length(addr, val);
+                self.update_channels(addr, val);
+            },
+            _ => (),
+        }
+    }
+
+    // True while the APU is powered on through NR52
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    // Run the next frame sequencer step. Length counters are clocked on every other step, 256 times a second
+    pub fn step_frame_sequencer(&mut self) {
+        if self.frame_step & 1 == 0 {
+            for (channel, &enable_reg) in LENGTH_ENABLE.iter().enumerate() {
+                let length_enabled = self.regs[enable_reg] & 0b0100_0000 != 0;
+                if length_enabled && self.lengths[channel] > 0 {
+                    self.lengths[channel] -= 1;
+                    if self.lengths[channel] == 0 {
+                        self.channels &= !(1 << channel);
+                    }
+                }
+            }
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    // Load a length counter from NR11, NR21, NR31 or NR41, the counter runs for the remainder of 64 (256 on the
+    // wave channel) steps
+    fn load_length(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF11 => self.lengths[0] = 64 - (val & 0b0011_1111) as u16,
+            0xFF16 => self.lengths[1] = 64 - (val & 0b0011_1111) as u16,
+            0xFF1B => self.lengths[2] = 256 - val as u16,
+            0xFF20 => self.lengths[3] = 64 - (val & 0b0011_1111) as u16,
+            _ => (),
+        }
+    }
+
+    // Track the channel status bits in NR52 for writes to the volume and trigger registers
+    fn update_channels(&mut self, addr: u16, val: u8) {
+        let (channel, dac_on) = match addr {
+            0xFF12 | 0xFF14 => (0, self.regs[0x02] & 0xF8 != 0),
+            0xFF17 | 0xFF19 => (1, self.regs[0x07] & 0xF8 != 0),
+            0xFF1A | 0xFF1E => (2, self.regs[0x0A] & 0x80 != 0),
+            0xFF21 | 0xFF23 => (3, self.regs[0x11] & 0xF8 != 0),
+            _ => return,
+        };
+        let trigger = matches!(addr, 0xFF14 | 0xFF19 | 0xFF1E | 0xFF23) && val & 0b1000_0000 != 0;
+        if trigger && self.lengths[channel] == 0 {
+            // Triggering a channel whose length ran out starts a full length
+            self.lengths[channel] = if channel == 2 { 256 } else { 64 };
+        }
+        if !dac_on {
+            // Turning the DAC off disables the channel
+            self.channels &= !(1 << channel);
+        } else if trigger {
+            self.channels |= 1 << channel;
+        }
+    }
+}
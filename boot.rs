@@ -0,0 +1,182 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cartridge::CartridgeHeader;
+
+// Size of the DMG, MGB and SGB boot ROMs, mapped at 0x0000-0x00FF
+pub const BOOT_ROM_SIZE: usize = 0x100;
+// Size of the CGB boot ROM, mapped at 0x0000-0x00FF and 0x0200-0x08FF with the cartridge header showing through in between
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+// Hardware model being emulated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    // Original Game Boy
+    Dmg,
+    // Game Boy Pocket and Light
+    Mgb,
+    // Super Game Boy
+    Sgb,
+    // Game Boy Color
+    Cgb,
+}
+
+impl Model {
+    // True for models with the Game Boy Color hardware
+    pub fn is_cgb(&self) -> bool {
+        *self == Model::Cgb
+    }
+}
+
+// CPU register values left behind by the boot ROM
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PostBootRegisters {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+impl PostBootRegisters {
+    // Register values after the boot ROM of the given model ran the given cartridge
+    pub fn new(model: Model, header: &CartridgeHeader) -> PostBootRegisters {
+        // The DMG and MGB boot ROMs leave H and C set unless the header checksum is 0
+        let dmg_flags = if header.header_checksum == 0 { 0x80 } else { 0xB0 };
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::Dmg => (0x01, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Mgb => (0xFF, dmg_flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Cgb if header.supports_cgb() => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+            // Running a DMG game in compatibility mode
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7C),
+        };
+        PostBootRegisters { a, f, b, c, d, e, h, l, sp: 0xFFFE, pc: 0x0100 }
+    }
+}
+
+// I/O register values left behind by the boot ROM, as (address, value) pairs
+pub fn post_boot_io(model: Model) -> Vec<(u16, u8)> {
+    let cgb = model.is_cgb();
+    vec![
+        (0xFF00, 0xCF), // P1
+        (0xFF01, 0x00), // SB
+        (0xFF02, if cgb { 0x7F } else { 0x7E }), // SC
+        (0xFF04, if cgb { 0x00 } else { 0xAB }), // DIV
+        (0xFF05, 0x00), // TIMA
+        (0xFF06, 0x00), // TMA
+        (0xFF07, 0xF8), // TAC
+        (0xFF0F, 0xE1), // IF
+        (0xFF26, if model == Model::Sgb { 0xF0 } else { 0xF1 }), // NR52, the APU must be on before the other sound registers can be written
+        (0xFF10, 0x80), // NR10
+        (0xFF11, 0xBF), // NR11
+        (0xFF12, 0xF3), // NR12
+        (0xFF13, 0xFF), // NR13
+        (0xFF14, 0xBF), // NR14
+        (0xFF16, 0x3F), // NR21
+        (0xFF17, 0x00), // NR22
+        (0xFF18, 0xFF), // NR23
+        (0xFF19, 0xBF), // NR24
+        (0xFF1A, 0x7F), // NR30
+        (0xFF1B, 0xFF), // NR31
+        (0xFF1C, 0x9F), // NR32
+        (0xFF1D, 0xFF), // NR33
+        (0xFF1E, 0xBF), // NR34
+        (0xFF20, 0xFF), // NR41
+        (0xFF21, 0x00), // NR42
+        (0xFF22, 0x00), // NR43
+        (0xFF23, 0xBF), // NR44
+        (0xFF24, 0x77), // NR50
+        (0xFF25, 0xF3), // NR51
+        (0xFF40, 0x91), // LCDC
+        (0xFF41, if cgb { 0x81 } else { 0x85 }), // STAT
+        (0xFF42, 0x00), // SCY
+        (0xFF43, 0x00), // SCX
+        (0xFF44, 0x00), // LY
+        (0xFF45, 0x00), // LYC
+        (0xFF46, 0xFF), // DMA
+        (0xFF47, 0xFC), // BGP
+        (0xFF48, 0xFF), // OBP0
+        (0xFF49, 0xFF), // OBP1
+        (0xFF4A, 0x00), // WY
+        (0xFF4B, 0x00), // WX
+        (0xFFFF, 0x00), // IE
+    ]
+}
+
+// Errors that can occur while loading a boot ROM
+#[derive(Debug)]
+pub enum BootRomError {
+    // The boot ROM file could not be read
+    Io(io::Error),
+    // The image is neither a 256-byte DMG nor a 2304-byte CGB boot ROM
+    InvalidSize(usize),
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootRomError::Io(err) => write!(f, "{}", err),
+            BootRomError::InvalidSize(len) => write!(f, "boot ROM is {} bytes, expected {} or {} bytes", len, BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE),
+        }
+    }
+}
+
+impl error::Error for BootRomError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BootRomError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BootRomError {
+    fn from(err: io::Error) -> BootRomError {
+        BootRomError::Io(err)
+    }
+}
+
+// Boot ROM image overlaid on the start of the cartridge ROM until 0xFF50 is written
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    // Create a boot ROM from an image held in memory
+    pub fn from_bytes(data: &[u8]) -> Result<BootRom, BootRomError> {
+        if data.len() != BOOT_ROM_SIZE && data.len() != CGB_BOOT_ROM_SIZE {
+            return Err(BootRomError::InvalidSize(data.len()));
+        }
+        Ok(BootRom { data: data.to_vec() })
+    }
+
+    // Load a boot ROM from a file on disk
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<BootRom, BootRomError> {
+        let data = fs::read(path)?;
+        BootRom::from_bytes(&data)
+    }
+
+    // True for the CGB boot ROM with its second part at 0x0200-0x08FF
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_ROM_SIZE
+    }
+
+    // Read a byte if the address is covered by the boot ROM, None where the cartridge shows through
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        let addr = addr as usize;
+        if addr < BOOT_ROM_SIZE || (self.is_cgb() && (0x0200..CGB_BOOT_ROM_SIZE).contains(&addr)) {
+            Some(self.data[addr])
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use crate::boot::{BootRom, BootRomError, Model, PostBootRegisters};
+use crate::cartridge_tests::{build_rom, fix_checksums};
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+
+#[cfg(test)]
+pub mod tests {
+    use super::*; // Import the functions and types from the parent module
+
+    #[test]
+    pub fn test_boot_rom_overlay() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x00, 0x00, 0x00)).unwrap();
+        memory.load_boot_rom(BootRom::from_bytes(&[0x31; 0x100]).unwrap());
+
+        // The boot ROM covers 0x0000-0x00FF, the cartridge header is visible behind it
+        assert_eq!(memory.read_byte(0x0000), 0x31);
+        assert_eq!(memory.read_byte(0x00FF), 0x31);
+        assert_eq!(memory.read_byte(0x0104), 0xCE);
+
+        // Writing 0 to 0xFF50 does nothing, any other value unmaps the boot ROM for good
+        memory.write_byte(0xFF50, 0x00);
+        assert!(memory.boot_rom_mapped());
+        memory.write_byte(0xFF50, 0x01);
+        assert!(!memory.boot_rom_mapped());
+        assert_eq!(memory.read_byte(0x0000), 0x00);
+        memory.write_byte(0xFF50, 0x00);
+        assert_eq!(memory.read_byte(0x0000), 0x00);
+    }
+
+    #[test]
+    pub fn test_cgb_boot_rom_overlay() {
+        let mut memory = Memory::new();
+        memory.set_model(Model::Cgb);
+        memory.load_rom(&build_rom(0x00, 0x00, 0x00)).unwrap();
+        memory.load_boot_rom(BootRom::from_bytes(&[0x31; 0x900]).unwrap());
+
+        // The CGB boot ROM has a second part at 0x0200-0x08FF around the cartridge header
+        assert_eq!(memory.read_byte(0x0000), 0x31);
+        assert_eq!(memory.read_byte(0x0147), 0x00);
+        assert_eq!(memory.read_byte(0x0200), 0x31);
+        assert_eq!(memory.read_byte(0x08FF), 0x31);
+        assert_eq!(memory.read_byte(0x0900), 0x00);
+
+        assert!(matches!(BootRom::from_bytes(&[0; 0x200]), Err(BootRomError::InvalidSize(0x200))));
+    }
+
+    #[test]
+    pub fn test_skip_boot() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x00, 0x00, 0x00)).unwrap();
+        memory.load_boot_rom(BootRom::from_bytes(&[0x31; 0x100]).unwrap());
+
+        let regs = memory.skip_boot();
+        assert!(!memory.boot_rom_mapped());
+        assert_eq!((regs.a, regs.f, regs.sp, regs.pc), (0x01, 0xB0, 0xFFFE, 0x0100));
+        assert_eq!(memory.read_byte(0xFF40), 0x91);
+        assert_eq!(memory.read_byte(0xFF47), 0xFC);
+        assert_eq!(memory.read_byte(0xFF26), 0xF1);
+
+        // Switching the LCD on while replaying the registers leaves no STAT interrupt pending, on either model
+        assert_eq!(memory.read_byte(0xFF0F), 0xE1);
+        let mut memory = Memory::new();
+        memory.set_model(Model::Cgb);
+        memory.load_rom(&build_rom(0x00, 0x00, 0x00)).unwrap();
+        memory.skip_boot();
+        assert_eq!(memory.read_byte(0xFF0F), 0xE1);
+
+        // The CGB boot ROM leaves A = 0x11 so games can detect it
+        let header = memory.cartridge().header().clone();
+        assert_eq!(PostBootRegisters::new(Model::Cgb, &header).a, 0x11);
+        assert_eq!(PostBootRegisters::new(Model::Mgb, &header).a, 0xFF);
+    }
+
+    #[test]
+    pub fn test_skip_boot_runs_entry_point() {
+        // JP 0x0150 at the entry point
+        let mut rom = build_rom(0x00, 0x00, 0x00);
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        fix_checksums(&mut rom);
+        let mut memory = Memory::new();
+        memory.load_rom(&rom).unwrap();
+
+        // No OAM DMA is left running to take over the bus while the first instructions are fetched
+        let mut cpu = Cpu::from_registers(memory.skip_boot());
+        assert_eq!(memory.read_byte(0xFF46), 0xFF);
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.pc, 0x0150);
+    }
+
+    #[test]
+    pub fn test_skip_boot_cgb_game() {
+        let mut rom = build_rom(0x00, 0x00, 0x00);
+        rom[0x0143] = 0x80;
+        fix_checksums(&mut rom);
+        let mut memory = Memory::new();
+        memory.load_rom(&rom).unwrap();
+
+        // Started without a boot ROM, a CGB game runs on a CGB as main does
+        assert!(memory.cartridge().header().supports_cgb());
+        memory.set_model(Model::Cgb);
+        let regs = memory.skip_boot();
+        assert_eq!((regs.a, regs.f, regs.b, regs.e), (0x11, 0x80, 0x00, 0x56));
+
+        // VRAM and WRAM are banked
+        memory.write_byte(0xFF4F, 1);
+        memory.write_byte(0x8000, 0x12);
+        memory.write_byte(0xFF70, 2);
+        memory.write_byte(0xD000, 0x34);
+        memory.write_byte(0xFF4F, 0);
+        memory.write_byte(0xFF70, 1);
+        assert_eq!(memory.read_byte(0x8000), 0x00);
+        assert_eq!(memory.read_byte(0xD000), 0x00);
+        memory.write_byte(0xFF4F, 1);
+        memory.write_byte(0xFF70, 2);
+        assert_eq!(memory.read_byte(0x8000), 0x12);
+        assert_eq!(memory.read_byte(0xD000), 0x34);
+    }
+}
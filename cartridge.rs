@@ -0,0 +1,284 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Size of a single switchable ROM bank
+pub const ROM_BANK_SIZE: usize = 0x4000;
+// Largest ROM image any cartridge can hold (512 banks of 16KB)
+pub const MAX_ROM_SIZE: usize = 0x80_0000;
+// The header occupies 0x0100-0x014F, anything shorter cannot be a cartridge
+pub const HEADER_END: usize = 0x0150;
+
+// Errors that can occur while loading a cartridge
+#[derive(Debug)]
+pub enum CartridgeError {
+    // The ROM file could not be read
+    Io(io::Error),
+    // The image is too short to contain a header
+    Truncated { len: usize },
+    // The image is larger than any MBC can address
+    TooLarge { len: usize },
+    // The header checksum at 0x014D does not match the header contents
+    HeaderChecksum { expected: u8, actual: u8 },
+    // The global checksum at 0x014E-0x014F does not match the ROM contents
+    GlobalChecksum { expected: u16, actual: u16 },
+    // The ROM size byte at 0x0148 is not a known value
+    InvalidRomSize(u8),
+    // The RAM size byte at 0x0149 is not a known value
+    InvalidRamSize(u8),
+    // The cartridge type byte at 0x0147 names hardware we do not emulate
+    UnsupportedCartridgeType(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(err) => write!(f, "{}", err),
+            CartridgeError::Truncated { len } => write!(f, "ROM image is {} bytes, too short to hold a cartridge header", len),
+            CartridgeError::TooLarge { len } => write!(f, "ROM image is {} bytes, the maximum is {} bytes", len, MAX_ROM_SIZE),
+            CartridgeError::HeaderChecksum { expected, actual } => write!(f, "header checksum mismatch: header says {:02X}, computed {:02X}", expected, actual),
+            CartridgeError::GlobalChecksum { expected, actual } => write!(f, "global checksum mismatch: header says {:04X}, computed {:04X}", expected, actual),
+            CartridgeError::InvalidRomSize(code) => write!(f, "invalid ROM size code {:02X}", code),
+            CartridgeError::InvalidRamSize(code) => write!(f, "invalid RAM size code {:02X}", code),
+            CartridgeError::UnsupportedCartridgeType(code) => write!(f, "unsupported cartridge type {:02X}", code),
+        }
+    }
+}
+
+impl error::Error for CartridgeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            CartridgeError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> CartridgeError {
+        CartridgeError::Io(err)
+    }
+}
+
+// Parsed cartridge header (0x0100-0x014F)
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    // Game title in upper case ASCII
+    pub title: String,
+    // Four character manufacturer code, only present on newer cartridges
+    pub manufacturer_code: Option<String>,
+    // CGB flag at 0x0143 (0x80 = CGB enhanced, 0xC0 = CGB only)
+    pub cgb_flag: u8,
+    // SGB flag at 0x0146, true when the game supports SGB functions
+    pub sgb: bool,
+    // Cartridge type byte at 0x0147, selects the MBC and extra hardware
+    pub cartridge_type: u8,
+    // ROM size in bytes, decoded from 0x0148
+    pub rom_size: usize,
+    // External RAM size in bytes, decoded from 0x0149
+    pub ram_size: usize,
+    // Destination code at 0x014A (0x00 = Japan, 0x01 = overseas)
+    pub destination: u8,
+    // Old licensee code at 0x014B
+    pub old_licensee: u8,
+    // New licensee code at 0x0144-0x0145, used when the old code is 0x33
+    pub new_licensee: Option<String>,
+    // Mask ROM version number at 0x014C
+    pub version: u8,
+    // Header checksum at 0x014D
+    pub header_checksum: u8,
+    // Global checksum at 0x014E-0x014F
+    pub global_checksum: u16,
+}
+
+impl CartridgeHeader {
+    // Parse and validate the header of a ROM image
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated { len: rom.len() });
+        }
+
+        let actual = CartridgeHeader::compute_header_checksum(rom);
+        if actual != rom[0x014D] {
+            return Err(CartridgeError::HeaderChecksum { expected: rom[0x014D], actual });
+        }
+
+        let cgb_flag = rom[0x0143];
+        let old_licensee = rom[0x014B];
+        // Newer cartridges shortened the title to make room for the manufacturer code and CGB flag
+        let (title_end, manufacturer_code) = if cgb_flag & 0x80 != 0 && old_licensee == 0x33 {
+            (0x013F, Some(ascii_string(&rom[0x013F..0x0143])))
+        } else if cgb_flag & 0x80 != 0 {
+            (0x0143, None)
+        } else {
+            (0x0144, None)
+        };
+
+        Ok(CartridgeHeader {
+            title: ascii_string(&rom[0x0134..title_end]),
+            manufacturer_code,
+            cgb_flag,
+            sgb: rom[0x0146] == 0x03,
+            cartridge_type: rom[0x0147],
+            rom_size: decode_rom_size(rom[0x0148])?,
+            ram_size: decode_ram_size(rom[0x0149])?,
+            destination: rom[0x014A],
+            old_licensee,
+            new_licensee: if old_licensee == 0x33 { Some(ascii_string(&rom[0x0144..0x0146])) } else { None },
+            version: rom[0x014C],
+            header_checksum: rom[0x014D],
+            global_checksum: ((rom[0x014E] as u16) << 8) | rom[0x014F] as u16,
+        })
+    }
+
+    // Compute the header checksum over 0x0134-0x014C the same way the boot ROM does
+    pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+        rom[0x0134..=0x014C].iter().fold(0u8, |acc, &b| acc.wrapping_sub(b).wrapping_sub(1))
+    }
+
+    // Compute the global checksum, the sum of every ROM byte except the checksum itself
+    pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x014E && i != 0x014F)
+            .fold(0u16, |acc, (_, &b)| acc.wrapping_add(b as u16))
+    }
+
+    // Verify the global checksum, real hardware never checks it so loading does not require it
+    pub fn verify_global_checksum(&self, rom: &[u8]) -> Result<(), CartridgeError> {
+        let actual = CartridgeHeader::compute_global_checksum(rom);
+        if actual != self.global_checksum {
+            return Err(CartridgeError::GlobalChecksum { expected: self.global_checksum, actual });
+        }
+        Ok(())
+    }
+
+    // True for CGB enhanced and CGB only games
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+
+    // True when the cartridge has a battery keeping its RAM (and clock) alive
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF)
+    }
+
+    // True when the cartridge has a real-time clock
+    pub fn has_timer(&self) -> bool {
+        matches!(self.cartridge_type, 0x0F | 0x10)
+    }
+
+    // True when the cartridge has a rumble motor
+    pub fn has_rumble(&self) -> bool {
+        matches!(self.cartridge_type, 0x1C..=0x1E)
+    }
+}
+
+// Decode the ROM size byte into a size in bytes
+fn decode_rom_size(code: u8) -> Result<usize, CartridgeError> {
+    match code {
+        0x00..=0x08 => Ok((2 * ROM_BANK_SIZE) << code),
+        // Unofficial sizes listed in some documentation
+        0x52 => Ok(72 * ROM_BANK_SIZE),
+        0x53 => Ok(80 * ROM_BANK_SIZE),
+        0x54 => Ok(96 * ROM_BANK_SIZE),
+        _ => Err(CartridgeError::InvalidRomSize(code)),
+    }
+}
+
+// Decode the RAM size byte into a size in bytes
+fn decode_ram_size(code: u8) -> Result<usize, CartridgeError> {
+    match code {
+        0x00 => Ok(0),
+        0x01 => Ok(0x800),
+        0x02 => Ok(0x2000),
+        0x03 => Ok(0x8000),
+        0x04 => Ok(0x20000),
+        0x05 => Ok(0x10000),
+        _ => Err(CartridgeError::InvalidRamSize(code)),
+    }
+}
+
+// Convert a zero padded header field into a string, dropping padding and non-printable bytes
+fn ascii_string(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&b| b != 0)
+        .filter(|b| b.is_ascii_graphic() || **b == b' ')
+        .map(|&b| b as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+// Cartridge holding the full ROM image, separate from the 64KB address space
+pub struct Cartridge {
+    // Complete ROM image as read from the file
+    rom: Vec<u8>,
+    // Parsed header of the ROM image
+    header: CartridgeHeader,
+    // File the ROM was loaded from, battery-backed saves are stored next to it
+    path: Option<PathBuf>,
+}
+
+impl Cartridge {
+    // Create an empty cartridge slot, reads return open bus (0xFF)
+    pub fn empty() -> Cartridge {
+        Cartridge { rom: Vec::new(), header: CartridgeHeader::default(), path: None }
+    }
+
+    // Create a cartridge from a ROM image held in memory
+    pub fn from_bytes(data: &[u8]) -> Result<Cartridge, CartridgeError> {
+        if data.len() > MAX_ROM_SIZE {
+            return Err(CartridgeError::TooLarge { len: data.len() });
+        }
+        let header = CartridgeHeader::parse(data)?;
+        Ok(Cartridge { rom: data.to_vec(), header, path: None })
+    }
+
+    // Load a cartridge from a ROM file on disk
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Cartridge, CartridgeError> {
+        let data = fs::read(&path)?;
+        let mut cartridge = Cartridge::from_bytes(&data)?;
+        cartridge.path = Some(path.as_ref().to_path_buf());
+        Ok(cartridge)
+    }
+
+    // Parsed header of the inserted cartridge
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    // Path of the .sav file for battery-backed cartridges loaded from a file
+    pub fn save_path(&self) -> Option<PathBuf> {
+        if !self.header.has_battery() {
+            return None;
+        }
+        self.path.as_ref().map(|path| path.with_extension("sav"))
+    }
+
+    // Size of the ROM image in bytes
+    pub fn rom_size(&self) -> usize {
+        self.rom.len()
+    }
+
+    // Number of 16KB banks in the ROM image, a partial last bank counts as a full one
+    pub fn rom_banks(&self) -> usize {
+        self.rom.len().div_ceil(ROM_BANK_SIZE)
+    }
+
+    // Read a byte at the given offset into the ROM image, offsets past the end wrap around
+    pub fn read_rom(&self, offset: usize) -> u8 {
+        if self.rom.is_empty() {
+            return 0xFF;
+        }
+        self.rom[offset % self.rom.len()]
+    }
+
+    // Read a byte from the given 16KB bank
+    pub fn read_rom_bank(&self, bank: usize, addr: u16) -> u8 {
+        self.read_rom(bank * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1)))
+    }
+}
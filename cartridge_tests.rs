@@ -0,0 +1,102 @@
+use crate::cartridge::{Cartridge, CartridgeError, CartridgeHeader};
+
+// Nintendo logo the boot ROM compares against 0x0104-0x0133
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// Recompute both checksums after the header or ROM contents were changed
+pub fn fix_checksums(rom: &mut [u8]) {
+    rom[0x014D] = CartridgeHeader::compute_header_checksum(rom);
+    let global = CartridgeHeader::compute_global_checksum(rom);
+    rom[0x014E] = (global >> 8) as u8;
+    rom[0x014F] = global as u8;
+}
+
+// Build a ROM image with a valid header, every byte outside the header holding its bank number
+pub fn build_rom(cartridge_type: u8, rom_size_code: u8, ram_size_code: u8) -> Vec<u8> {
+    let banks = 2 << rom_size_code;
+    let mut rom = vec![0; banks * 0x4000];
+    for (bank, chunk) in rom.chunks_mut(0x4000).enumerate() {
+        chunk.fill(bank as u8);
+    }
+    rom[0x0100..0x0150].fill(0);
+    rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+    rom[0x0134..0x0134 + 11].copy_from_slice(b"GPTBOY TEST");
+    rom[0x0147] = cartridge_type;
+    rom[0x0148] = rom_size_code;
+    rom[0x0149] = ram_size_code;
+    rom[0x014A] = 0x01;
+    rom[0x014B] = 0x01;
+    fix_checksums(&mut rom);
+    rom
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*; // Import the functions and types from the parent module
+
+    #[test]
+    pub fn test_parse_header() {
+        let mut rom = build_rom(0x03, 0x02, 0x03);
+        rom[0x0146] = 0x03;
+        rom[0x014C] = 0x02;
+        fix_checksums(&mut rom);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "GPTBOY TEST");
+        assert_eq!(header.manufacturer_code, None);
+        assert!(header.sgb);
+        assert!(!header.supports_cgb());
+        assert_eq!(header.cartridge_type, 0x03);
+        assert!(header.has_battery());
+        assert_eq!(header.rom_size, 128 * 1024);
+        assert_eq!(header.ram_size, 32 * 1024);
+        assert_eq!(header.destination, 0x01);
+        assert_eq!(header.new_licensee, None);
+        assert_eq!(header.version, 0x02);
+        assert!(header.verify_global_checksum(&rom).is_ok());
+    }
+
+    #[test]
+    pub fn test_parse_cgb_header() {
+        let mut rom = build_rom(0x00, 0x00, 0x00);
+        rom[0x013F..0x0143].copy_from_slice(b"AGBE");
+        rom[0x0143] = 0xC0;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        rom[0x014B] = 0x33;
+        fix_checksums(&mut rom);
+
+        // The title is shortened to make room for the manufacturer code
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "GPTBOY TEST");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AGBE"));
+        assert!(header.supports_cgb());
+        assert_eq!(header.new_licensee.as_deref(), Some("01"));
+    }
+
+    #[test]
+    pub fn test_invalid_headers() {
+        // Too short to contain a header
+        assert!(matches!(CartridgeHeader::parse(&[0; 0x100]), Err(CartridgeError::Truncated { len: 0x100 })));
+
+        // Corrupted header checksum
+        let mut rom = build_rom(0x00, 0x00, 0x00);
+        rom[0x014D] ^= 0xFF;
+        assert!(matches!(Cartridge::from_bytes(&rom), Err(CartridgeError::HeaderChecksum { .. })));
+
+        // Unknown ROM size code
+        let mut rom = build_rom(0x00, 0x00, 0x00);
+        rom[0x0148] = 0x20;
+        fix_checksums(&mut rom);
+        assert!(matches!(Cartridge::from_bytes(&rom), Err(CartridgeError::InvalidRomSize(0x20))));
+
+        // Global checksum is reported but does not stop loading
+        let mut rom = build_rom(0x00, 0x00, 0x00);
+        rom[0x4000] ^= 0xFF;
+        let cartridge = Cartridge::from_bytes(&rom).unwrap();
+        assert!(matches!(cartridge.header().verify_global_checksum(&rom), Err(CartridgeError::GlobalChecksum { .. })));
+    }
+}
@@ -0,0 +1,739 @@
+use crate::boot::PostBootRegisters;
+use crate::memory::{Memory, SPEED_SWITCH_CYCLES};
+
+// Flag bits in the F register
+pub const FLAG_Z: u8 = 0b1000_0000;
+pub const FLAG_N: u8 = 0b0100_0000;
+pub const FLAG_H: u8 = 0b0010_0000;
+pub const FLAG_C: u8 = 0b0001_0000;
+
+// Sharp SM83 CPU
+pub struct Cpu {
+    pub a: u8,
+    // Flags, only the upper 4 bits exist
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    // Interrupt master enable, set by EI and RETI and cleared by DI
+    pub ime: bool,
+    // Set by EI, IME only turns on once the instruction after EI has run
+    pub ime_pending: bool,
+    // Set by HALT, the CPU does nothing until an interrupt is pending
+    pub halted: bool,
+    // Set when HALT runs with IME off and an interrupt already pending, the next opcode byte is read twice
+    pub halt_bug: bool,
+    // Set by STOP, the CPU does nothing until a joypad line goes low
+    pub stopped: bool,
+    // Set by an illegal opcode, the CPU hangs until it is reset
+    pub locked: bool,
+    // M-cycles ticked so far by the instruction being executed
+    cycles: u32,
+}
+
+impl Cpu {
+    // CPU in its power-on state, starting at the boot ROM
+    pub fn new() -> Cpu {
+        Cpu {
+            a: 0,
+            f: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            h: 0,
+            l: 0,
+            sp: 0,
+            pc: 0,
+            ime: false,
+            ime_pending: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            locked: false,
+            cycles: 0,
+        }
+    }
+
+    // CPU with the register values the boot ROM leaves behind, for starting without one
+    pub fn from_registers(regs: PostBootRegisters) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.a = regs.a;
+        cpu.f = regs.f & 0xF0;
+        cpu.b = regs.b;
+        cpu.c = regs.c;
+        cpu.d = regs.d;
+        cpu.e = regs.e;
+        cpu.h = regs.h;
+        cpu.l = regs.l;
+        cpu.sp = regs.sp;
+        cpu.pc = regs.pc;
+        cpu
+    }
+
+    pub fn af(&self) -> u16 {
+        ((self.a as u16) << 8) | self.f as u16
+    }
+
+    pub fn bc(&self) -> u16 {
+        ((self.b as u16) << 8) | self.c as u16
+    }
+
+    pub fn de(&self) -> u16 {
+        ((self.d as u16) << 8) | self.e as u16
+    }
+
+    pub fn hl(&self) -> u16 {
+        ((self.h as u16) << 8) | self.l as u16
+    }
+
+    // Set AF, the low 4 bits of F always read as 0
+    pub fn set_af(&mut self, val: u16) {
+        self.a = (val >> 8) as u8;
+        self.f = val as u8 & 0xF0;
+    }
+
+    pub fn set_bc(&mut self, val: u16) {
+        self.b = (val >> 8) as u8;
+        self.c = val as u8;
+    }
+
+    pub fn set_de(&mut self, val: u16) {
+        self.d = (val >> 8) as u8;
+        self.e = val as u8;
+    }
+
+    pub fn set_hl(&mut self, val: u16) {
+        self.h = (val >> 8) as u8;
+        self.l = val as u8;
+    }
+
+    // True if the given flag is set
+    pub fn flag(&self, flag: u8) -> bool {
+        self.f & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.f |= flag;
+        } else {
+            self.f &= !flag;
+        }
+    }
+
+    // Replace all four flags at once
+    fn set_flags(&mut self, z: bool, n: bool, h: bool, c: bool) {
+        self.f = (z as u8) << 7 | (n as u8) << 6 | (h as u8) << 5 | (c as u8) << 4;
+    }
+
+    // Execute one instruction, returns the number of M-cycles it took. Every M-cycle advances the rest of
+    // the system as it happens, so memory accesses see the hardware state at their exact point in the instruction
+    pub fn step(&mut self, memory: &mut Memory) -> u32 {
+        self.cycles = 0;
+        if self.locked {
+            self.tick(memory);
+            return self.cycles;
+        }
+        if self.stopped {
+            // Any selected joypad line going low ends STOP
+            if memory.read_byte(0xFF00) & 0x0F == 0x0F {
+                self.tick(memory);
+                return self.cycles;
+            }
+            self.stopped = false;
+        }
+        if self.halted {
+            // A pending interrupt ends HALT even with IME off, execution then carries on after HALT
+            if memory.pending_interrupt().is_none() {
+                // Nothing changes until the next hardware event, so skip the whole-M-cycles up to it
+                let m_cycles = memory.cycles_until_next_event().map_or(1, |cycles| cycles.div_ceil(4).max(1));
+                memory.step(m_cycles as u32 * 4);
+                self.cycles += m_cycles as u32;
+                return self.cycles;
+            }
+            self.halted = false;
+        }
+        if self.ime && memory.pending_interrupt().is_some() {
+            self.service_interrupt(memory);
+            return self.cycles;
+        }
+        let ime_was_pending = self.ime_pending;
+        let opcode = if self.halt_bug {
+            // PC fails to move past the byte after HALT, so it is executed again
+            self.halt_bug = false;
+            self.read(memory, self.pc)
+        } else {
+            self.fetch_byte(memory)
+        };
+        self.execute(memory, opcode);
+        // EI takes effect after the instruction following it, unless that instruction was DI
+        if ime_was_pending && self.ime_pending {
+            self.ime = true;
+            self.ime_pending = false;
+        }
+        self.cycles
+    }
+
+    // Dispatch the highest priority pending interrupt, taking 5 M-cycles: two idle, two pushing PC and one jumping.
+    // The interrupt is picked after the high byte of PC is pushed, so a push that overwrites IE can cancel the
+    // dispatch and PC ends up at 0x0000
+    fn service_interrupt(&mut self, memory: &mut Memory) {
+        self.ime = false;
+        self.tick(memory);
+        self.tick(memory);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(memory, self.sp, (self.pc >> 8) as u8);
+        let interrupt = memory.pending_interrupt();
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(memory, self.sp, self.pc as u8);
+        self.pc = match interrupt {
+            Some(interrupt) => {
+                memory.acknowledge_interrupt(interrupt);
+                interrupt.vector()
+            },
+            None => 0x0000,
+        };
+        self.tick(memory);
+    }
+
+    // Let one M-cycle pass, advancing the rest of the system by 4 clock cycles
+    fn tick(&mut self, memory: &mut Memory) {
+        memory.step(4);
+        self.cycles += 1;
+    }
+
+    // Read a byte at the end of an M-cycle
+    fn read(&mut self, memory: &mut Memory, addr: u16) -> u8 {
+        self.tick(memory);
+        memory.read_byte(addr)
+    }
+
+    // Write a byte at the end of an M-cycle
+    fn write(&mut self, memory: &mut Memory, addr: u16, val: u8) {
+        self.tick(memory);
+        memory.write_byte(addr, val);
+    }
+
+    // Read the byte at PC and move past it
+    fn fetch_byte(&mut self, memory: &mut Memory) -> u8 {
+        let byte = self.read(memory, self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        byte
+    }
+
+    // Read the little-endian word at PC and move past it
+    fn fetch_word(&mut self, memory: &mut Memory) -> u16 {
+        let low = self.fetch_byte(memory) as u16;
+        let high = self.fetch_byte(memory) as u16;
+        (high << 8) | low
+    }
+
+    // Push a word, high byte first. Callers add the idle M-cycle that comes before it
+    fn push(&mut self, memory: &mut Memory, val: u16) {
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(memory, self.sp, (val >> 8) as u8);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write(memory, self.sp, val as u8);
+    }
+
+    fn pop(&mut self, memory: &mut Memory) -> u16 {
+        let low = self.read(memory, self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        let high = self.read(memory, self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        (high << 8) | low
+    }
+
+    // Read an 8-bit operand by its index in the opcode: B, C, D, E, H, L, (HL), A
+    fn read_r8(&mut self, memory: &mut Memory, index: u8) -> u8 {
+        match index {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => self.h,
+            5 => self.l,
+            6 => self.read(memory, self.hl()),
+            _ => self.a,
+        }
+    }
+
+    // Write an 8-bit operand by its index in the opcode
+    fn write_r8(&mut self, memory: &mut Memory, index: u8, val: u8) {
+        match index {
+            0 => self.b = val,
+            1 => self.c = val,
+            2 => self.d = val,
+            3 => self.e = val,
+            4 => self.h = val,
+            5 => self.l = val,
+            6 => self.write(memory, self.hl(), val),
+            _ => self.a = val,
+        }
+    }
+
+    // Read a register pair by its index in the opcode: BC, DE, HL, SP
+    fn read_r16(&self, index: u8) -> u16 {
+        match index {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.hl(),
+            _ => self.sp,
+        }
+    }
+
+    // Write a register pair by its index in the opcode
+    fn write_r16(&mut self, index: u8, val: u16) {
+        match index {
+            0 => self.set_bc(val),
+            1 => self.set_de(val),
+            2 => self.set_hl(val),
+            _ => self.sp = val,
+        }
+    }
+
+    // Evaluate a condition by its index in the opcode: NZ, Z, NC, C
+    fn condition(&self, index: u8) -> bool {
+        match index {
+            0 => !self.flag(FLAG_Z),
+            1 => self.flag(FLAG_Z),
+            2 => !self.flag(FLAG_C),
+            _ => self.flag(FLAG_C),
+        }
+    }
+
+    // Execute a fetched opcode, ticking the M-cycles that follow the opcode fetch
+    fn execute(&mut self, memory: &mut Memory, opcode: u8) {
+        // Opcodes are decoded from their bit fields: xx yyy zzz, with yyy split into pp q
+        let y = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+        let p = y >> 1;
+        match opcode {
+            // NOP
+            0x00 => (),
+            // LD (nn),SP
+            0x08 => {
+                let addr = self.fetch_word(memory);
+                self.write(memory, addr, self.sp as u8);
+                self.write(memory, addr.wrapping_add(1), (self.sp >> 8) as u8);
+            },
+            // STOP, followed by a byte that is skipped
+            0x10 => {
+                self.pc = self.pc.wrapping_add(1);
+                if memory.stop() {
+                    // Switching speed stops the CPU for a while and then carries on
+                    memory.step(SPEED_SWITCH_CYCLES);
+                    self.cycles += SPEED_SWITCH_CYCLES / 4;
+                } else {
+                    self.stopped = true;
+                }
+            },
+            // JR d
+            0x18 => {
+                let offset = self.fetch_byte(memory) as i8;
+                self.pc = self.pc.wrapping_add(offset as u16);
+                self.tick(memory);
+            },
+            // JR cc,d
+            0x20 | 0x28 | 0x30 | 0x38 => {
+                let offset = self.fetch_byte(memory) as i8;
+                if self.condition(y - 4) {
+                    self.pc = self.pc.wrapping_add(offset as u16);
+                    self.tick(memory);
+                }
+            },
+            // LD rr,nn
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                let val = self.fetch_word(memory);
+                self.write_r16(p, val);
+            },
+            // ADD HL,rr
+            0x09 | 0x19 | 0x29 | 0x39 => {
+                let hl = self.hl();
+                let val = self.read_r16(p);
+                let (result, carry) = hl.overflowing_add(val);
+                self.set_flag(FLAG_N, false);
+                self.set_flag(FLAG_H, (hl & 0x0FFF) + (val & 0x0FFF) > 0x0FFF);
+                self.set_flag(FLAG_C, carry);
+                self.set_hl(result);
+                self.tick(memory);
+            },
+            // LD (BC),A / LD (DE),A / LD (HL+),A / LD (HL-),A
+            0x02 | 0x12 | 0x22 | 0x32 => {
+                let addr = self.indirect_address(p);
+                self.write(memory, addr, self.a);
+            },
+            // LD A,(BC) / LD A,(DE) / LD A,(HL+) / LD A,(HL-)
+            0x0A | 0x1A | 0x2A | 0x3A => {
+                let addr = self.indirect_address(p);
+                self.a = self.read(memory, addr);
+            },
+            // INC rr
+            0x03 | 0x13 | 0x23 | 0x33 => {
+                let val = self.read_r16(p).wrapping_add(1);
+                self.write_r16(p, val);
+                self.tick(memory);
+            },
+            // DEC rr
+            0x0B | 0x1B | 0x2B | 0x3B => {
+                let val = self.read_r16(p).wrapping_sub(1);
+                self.write_r16(p, val);
+                self.tick(memory);
+            },
+            // INC r
+            0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => {
+                let val = self.read_r8(memory, y);
+                let result = val.wrapping_add(1);
+                self.set_flag(FLAG_Z, result == 0);
+                self.set_flag(FLAG_N, false);
+                self.set_flag(FLAG_H, val & 0x0F == 0x0F);
+                self.write_r8(memory, y, result);
+            },
+            // DEC r
+            0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => {
+                let val = self.read_r8(memory, y);
+                let result = val.wrapping_sub(1);
+                self.set_flag(FLAG_Z, result == 0);
+                self.set_flag(FLAG_N, true);
+                self.set_flag(FLAG_H, val & 0x0F == 0x00);
+                self.write_r8(memory, y, result);
+            },
+            // LD r,n
+            0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
+                let val = self.fetch_byte(memory);
+                self.write_r8(memory, y, val);
+            },
+            // RLCA / RRCA / RLA / RRA, like their CB versions but Z is always cleared
+            0x07 | 0x0F | 0x17 | 0x1F => {
+                self.a = self.rotate_shift(y, self.a);
+                self.set_flag(FLAG_Z, false);
+            },
+            // DAA
+            0x27 => self.daa(),
+            // CPL
+            0x2F => {
+                self.a = !self.a;
+                self.set_flag(FLAG_N, true);
+                self.set_flag(FLAG_H, true);
+            },
+            // SCF
+            0x37 => {
+                self.set_flag(FLAG_N, false);
+                self.set_flag(FLAG_H, false);
+                self.set_flag(FLAG_C, true);
+            },
+            // CCF
+            0x3F => {
+                let carry = self.flag(FLAG_C);
+                self.set_flag(FLAG_N, false);
+                self.set_flag(FLAG_H, false);
+                self.set_flag(FLAG_C, !carry);
+            },
+            // HALT
+            0x76 => {
+                if !self.ime && memory.pending_interrupt().is_some() {
+                    // With IME off and an interrupt already pending the CPU does not halt and hits the HALT bug
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            },
+            // LD r,r
+            0x40..=0x7F => {
+                let val = self.read_r8(memory, z);
+                self.write_r8(memory, y, val);
+            },
+            // ADD / ADC / SUB / SBC / AND / XOR / OR / CP A,r
+            0x80..=0xBF => {
+                let val = self.read_r8(memory, z);
+                self.alu(y, val);
+            },
+            // RET cc, the condition is checked in an M-cycle of its own
+            0xC0 | 0xC8 | 0xD0 | 0xD8 => {
+                self.tick(memory);
+                if self.condition(y) {
+                    self.pc = self.pop(memory);
+                    self.tick(memory);
+                }
+            },
+            // LDH (n),A
+            0xE0 => {
+                let addr = 0xFF00 | self.fetch_byte(memory) as u16;
+                self.write(memory, addr, self.a);
+            },
+            // ADD SP,d
+            0xE8 => {
+                self.sp = self.sp_plus_offset(memory);
+                self.tick(memory);
+                self.tick(memory);
+            },
+            // LDH A,(n)
+            0xF0 => {
+                let addr = 0xFF00 | self.fetch_byte(memory) as u16;
+                self.a = self.read(memory, addr);
+            },
+            // LD HL,SP+d
+            0xF8 => {
+                let val = self.sp_plus_offset(memory);
+                self.set_hl(val);
+                self.tick(memory);
+            },
+            // POP BC / POP DE / POP HL
+            0xC1 | 0xD1 | 0xE1 => {
+                let val = self.pop(memory);
+                self.write_r16(p & 0b11, val);
+            },
+            // POP AF
+            0xF1 => {
+                let val = self.pop(memory);
+                self.set_af(val);
+            },
+            // RET
+            0xC9 => {
+                self.pc = self.pop(memory);
+                self.tick(memory);
+            },
+            // RETI, enables interrupts straight away unlike EI
+            0xD9 => {
+                self.pc = self.pop(memory);
+                self.ime = true;
+                self.tick(memory);
+            },
+            // JP HL
+            0xE9 => self.pc = self.hl(),
+            // LD SP,HL
+            0xF9 => {
+                self.sp = self.hl();
+                self.tick(memory);
+            },
+            // JP cc,nn
+            0xC2 | 0xCA | 0xD2 | 0xDA => {
+                let addr = self.fetch_word(memory);
+                if self.condition(y) {
+                    self.pc = addr;
+                    self.tick(memory);
+                }
+            },
+            // LD (C),A
+            0xE2 => self.write(memory, 0xFF00 | self.c as u16, self.a),
+            // LD (nn),A
+            0xEA => {
+                let addr = self.fetch_word(memory);
+                self.write(memory, addr, self.a);
+            },
+            // LD A,(C)
+            0xF2 => self.a = self.read(memory, 0xFF00 | self.c as u16),
+            // LD A,(nn)
+            0xFA => {
+                let addr = self.fetch_word(memory);
+                self.a = self.read(memory, addr);
+            },
+            // JP nn
+            0xC3 => {
+                self.pc = self.fetch_word(memory);
+                self.tick(memory);
+            },
+            // CB prefix
+            0xCB => {
+                let opcode = self.fetch_byte(memory);
+                self.execute_cb(memory, opcode);
+            },
+            // DI, also cancels an EI that has not taken effect yet
+            0xF3 => {
+                self.ime = false;
+                self.ime_pending = false;
+            },
+            // EI
+            0xFB => self.ime_pending = true,
+            // CALL cc,nn
+            0xC4 | 0xCC | 0xD4 | 0xDC => {
+                let addr = self.fetch_word(memory);
+                if self.condition(y) {
+                    self.tick(memory);
+                    self.push(memory, self.pc);
+                    self.pc = addr;
+                }
+            },
+            // PUSH BC / PUSH DE / PUSH HL
+            0xC5 | 0xD5 | 0xE5 => {
+                self.tick(memory);
+                self.push(memory, self.read_r16(p & 0b11));
+            },
+            // PUSH AF
+            0xF5 => {
+                self.tick(memory);
+                self.push(memory, self.af());
+            },
+            // CALL nn
+            0xCD => {
+                let addr = self.fetch_word(memory);
+                self.tick(memory);
+                self.push(memory, self.pc);
+                self.pc = addr;
+            },
+            // ADD / ADC / SUB / SBC / AND / XOR / OR / CP A,n
+            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+                let val = self.fetch_byte(memory);
+                self.alu(y, val);
+            },
+            // RST
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+                self.tick(memory);
+                self.push(memory, self.pc);
+                self.pc = y as u16 * 8;
+            },
+            // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC and 0xFD do not exist and hang the CPU
+            _ => self.locked = true,
+        }
+    }
+
+    // Execute a CB-prefixed opcode fetched after the prefix
+    fn execute_cb(&mut self, memory: &mut Memory, opcode: u8) {
+        let y = (opcode >> 3) & 0b111;
+        let z = opcode & 0b111;
+        let val = self.read_r8(memory, z);
+        match opcode >> 6 {
+            // RLC / RRC / RL / RR / SLA / SRA / SWAP / SRL
+            0b00 => {
+                let result = self.rotate_shift(y, val);
+                self.write_r8(memory, z, result);
+            },
+            // BIT, only reads its operand
+            0b01 => {
+                self.set_flag(FLAG_Z, val & (1 << y) == 0);
+                self.set_flag(FLAG_N, false);
+                self.set_flag(FLAG_H, true);
+            },
+            // RES
+            0b10 => self.write_r8(memory, z, val & !(1 << y)),
+            // SET
+            _ => self.write_r8(memory, z, val | (1 << y)),
+        }
+    }
+
+    // Address for the LD (rr),A and LD A,(rr) group: BC, DE, then HL incremented or decremented afterwards
+    fn indirect_address(&mut self, index: u8) -> u16 {
+        match index {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => {
+                let hl = self.hl();
+                self.set_hl(hl.wrapping_add(1));
+                hl
+            },
+            _ => {
+                let hl = self.hl();
+                self.set_hl(hl.wrapping_sub(1));
+                hl
+            },
+        }
+    }
+
+    // 8-bit arithmetic and logic on A, selected by its index in the opcode
+    fn alu(&mut self, op: u8, val: u8) {
+        let a = self.a;
+        let carry = self.flag(FLAG_C) as u8;
+        match op {
+            // ADD
+            0 => {
+                let (result, c) = a.overflowing_add(val);
+                self.set_flags(result == 0, false, (a & 0x0F) + (val & 0x0F) > 0x0F, c);
+                self.a = result;
+            },
+            // ADC
+            1 => {
+                let result = a as u16 + val as u16 + carry as u16;
+                self.set_flags(result as u8 == 0, false, (a & 0x0F) + (val & 0x0F) + carry > 0x0F, result > 0xFF);
+                self.a = result as u8;
+            },
+            // SUB
+            2 => {
+                let (result, c) = a.overflowing_sub(val);
+                self.set_flags(result == 0, true, a & 0x0F < val & 0x0F, c);
+                self.a = result;
+            },
+            // SBC
+            3 => {
+                let result = a as i16 - val as i16 - carry as i16;
+                self.set_flags(result as u8 == 0, true, ((a & 0x0F) as i16) - ((val & 0x0F) as i16) - (carry as i16) < 0, result < 0);
+                self.a = result as u8;
+            },
+            // AND
+            4 => {
+                self.a = a & val;
+                self.set_flags(self.a == 0, false, true, false);
+            },
+            // XOR
+            5 => {
+                self.a = a ^ val;
+                self.set_flags(self.a == 0, false, false, false);
+            },
+            // OR
+            6 => {
+                self.a = a | val;
+                self.set_flags(self.a == 0, false, false, false);
+            },
+            // CP, a subtraction that only sets the flags
+            _ => {
+                let (result, c) = a.overflowing_sub(val);
+                self.set_flags(result == 0, true, a & 0x0F < val & 0x0F, c);
+            },
+        }
+    }
+
+    // Rotate and shift operations of the CB group, selected by their index in the opcode
+    fn rotate_shift(&mut self, op: u8, val: u8) -> u8 {
+        let carry = self.flag(FLAG_C) as u8;
+        let (result, c) = match op {
+            // RLC
+            0 => (val.rotate_left(1), val & 0x80 != 0),
+            // RRC
+            1 => (val.rotate_right(1), val & 0x01 != 0),
+            // RL, through the carry flag
+            2 => ((val << 1) | carry, val & 0x80 != 0),
+            // RR, through the carry flag
+            3 => ((val >> 1) | (carry << 7), val & 0x01 != 0),
+            // SLA
+            4 => (val << 1, val & 0x80 != 0),
+            // SRA, keeps the sign bit
+            5 => ((val >> 1) | (val & 0x80), val & 0x01 != 0),
+            // SWAP
+            6 => (val.rotate_left(4), false),
+            // SRL
+            _ => (val >> 1, val & 0x01 != 0),
+        };
+        self.set_flags(result == 0, false, false, c);
+        result
+    }
+
+    // Adjust A to binary-coded decimal after an addition or subtraction
+    fn daa(&mut self) {
+        let mut adjust = 0;
+        let mut carry = self.flag(FLAG_C);
+        let subtract = self.flag(FLAG_N);
+        if self.flag(FLAG_H) || (!subtract && self.a & 0x0F > 0x09) {
+            adjust |= 0x06;
+        }
+        if carry || (!subtract && self.a > 0x99) {
+            adjust |= 0x60;
+            carry = true;
+        }
+        self.a = if subtract { self.a.wrapping_sub(adjust) } else { self.a.wrapping_add(adjust) };
+        self.set_flag(FLAG_Z, self.a == 0);
+        self.set_flag(FLAG_H, false);
+        self.set_flag(FLAG_C, carry);
+    }
+
+    // SP plus a signed immediate byte for ADD SP,d and LD HL,SP+d. H and C come from the unsigned addition of the low byte
+    fn sp_plus_offset(&mut self, memory: &mut Memory) -> u16 {
+        let offset = self.fetch_byte(memory);
+        let sp = self.sp;
+        self.set_flags(false, false, (sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F, (sp & 0xFF) + offset as u16 > 0xFF);
+        sp.wrapping_add(offset as i8 as u16)
+    }
+}
@@ -0,0 +1,395 @@
+use crate::cpu::{Cpu, FLAG_C, FLAG_H, FLAG_N, FLAG_Z};
+use crate::memory::{Interrupt, Memory};
+
+#[cfg(test)]
+pub mod tests {
+    use super::*; // Import the functions and types from the parent module
+
+    // Load a program into work RAM at 0xC000 and point the CPU at it
+    fn setup(program: &[u8]) -> (Cpu, Memory) {
+        let mut memory = Memory::new();
+        for (i, &byte) in program.iter().enumerate() {
+            memory.write_byte(0xC000 + i as u16, byte);
+        }
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xC000;
+        cpu.sp = 0xFFFE;
+        (cpu, memory)
+    }
+
+    // Run instructions until PC reaches the end of the program, returns the M-cycles taken
+    fn run(cpu: &mut Cpu, memory: &mut Memory, len: usize) -> u32 {
+        let mut cycles = 0;
+        while cpu.pc < 0xC000 + len as u16 {
+            cycles += cpu.step(memory);
+        }
+        cycles
+    }
+
+    #[test]
+    pub fn test_cpu_loads() {
+        let program = [
+            0x01, 0x34, 0x12, // LD BC,0x1234
+            0x11, 0x00, 0xD0, // LD DE,0xD000
+            0x3E, 0x42, // LD A,0x42
+            0x12, // LD (DE),A
+            0x21, 0x10, 0xD0, // LD HL,0xD010
+            0x36, 0x99, // LD (HL),0x99
+            0x7E, // LD A,(HL)
+            0x22, // LD (HL+),A
+            0x32, // LD (HL-),A
+            0x08, 0x20, 0xD0, // LD (0xD020),SP
+            0xE0, 0x80, // LDH (0x80),A
+            0x50, // LD D,B
+        ];
+        let (mut cpu, mut memory) = setup(&program);
+        let cycles = run(&mut cpu, &mut memory, program.len());
+        assert_eq!(cpu.bc(), 0x1234);
+        assert_eq!(memory.read_byte(0xD000), 0x42);
+        assert_eq!(cpu.a, 0x99);
+        assert_eq!(memory.read_byte(0xD011), 0x99);
+        assert_eq!(cpu.hl(), 0xD010);
+        assert_eq!(memory.read_word(0xD020), 0xFFFE);
+        assert_eq!(memory.read_byte(0xFF80), 0x99);
+        assert_eq!(cpu.d, 0x12);
+        assert_eq!(cycles, 3 + 3 + 2 + 2 + 3 + 3 + 2 + 2 + 2 + 5 + 3 + 1);
+    }
+
+    #[test]
+    pub fn test_cpu_stack() {
+        let program = [
+            0x01, 0xFF, 0x12, // LD BC,0x12FF
+            0xC5, // PUSH BC
+            0xF1, // POP AF
+            0xF5, // PUSH AF
+            0xD1, // POP DE
+            0xF8, 0xFE, // LD HL,SP-2
+            0xE8, 0x02, // ADD SP,2
+        ];
+        let (mut cpu, mut memory) = setup(&program);
+        let cycles = run(&mut cpu, &mut memory, program.len());
+
+        // The low 4 bits of F do not exist
+        assert_eq!(cpu.a, 0x12);
+        assert_eq!(cpu.de(), 0x12F0);
+        assert_eq!(cpu.hl(), 0xFFFC);
+        assert_eq!(cpu.sp, 0x0000);
+        // H and C come from the low byte: 0xFE + 0x02 carries out of both nibbles
+        assert_eq!(cpu.f, FLAG_H | FLAG_C);
+        assert_eq!(cycles, 3 + 4 + 3 + 4 + 3 + 3 + 4);
+    }
+
+    #[test]
+    pub fn test_cpu_arithmetic() {
+        let (mut cpu, mut memory) = setup(&[
+            0xC6, 0x0F, // ADD A,0x0F
+            0xCE, 0xF1, // ADC A,0xF1
+            0xD6, 0x01, // SUB 0x01
+            0xDE, 0x00, // SBC A,0x00
+            0xFE, 0xFF, // CP 0xFF
+        ]);
+        cpu.a = 0x01;
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x10, FLAG_H));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x01, FLAG_C));
+        cpu.f = FLAG_C;
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x00, FLAG_Z | FLAG_N));
+        cpu.f = FLAG_C;
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0xFF, FLAG_N | FLAG_H | FLAG_C));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0xFF, FLAG_Z | FLAG_N));
+
+        // 8-bit INC and DEC leave C alone, 16-bit ones touch no flags
+        let (mut cpu, mut memory) = setup(&[0x3C, 0x05, 0x0B, 0x29]);
+        cpu.a = 0xFF;
+        cpu.b = 0x00;
+        cpu.f = FLAG_C;
+        cpu.set_hl(0x8800);
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x00, FLAG_Z | FLAG_H | FLAG_C));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.b, cpu.f), (0xFF, FLAG_N | FLAG_H | FLAG_C));
+        assert_eq!(cpu.step(&mut memory), 2);
+        assert_eq!((cpu.bc(), cpu.f), (0xFEFF, FLAG_N | FLAG_H | FLAG_C));
+
+        // ADD HL,HL keeps Z and sets H from bit 11
+        cpu.f = FLAG_Z;
+        assert_eq!(cpu.step(&mut memory), 2);
+        assert_eq!((cpu.hl(), cpu.f), (0x1000, FLAG_Z | FLAG_H | FLAG_C));
+    }
+
+    #[test]
+    pub fn test_cpu_daa() {
+        // 0x19 + 0x28 = 0x47 in BCD
+        let (mut cpu, mut memory) = setup(&[0xC6, 0x28, 0x27]);
+        cpu.a = 0x19;
+        run(&mut cpu, &mut memory, 3);
+        assert_eq!((cpu.a, cpu.f), (0x47, 0));
+
+        // 0x90 + 0x20 = 0x10 with a decimal carry
+        let (mut cpu, mut memory) = setup(&[0xC6, 0x20, 0x27]);
+        cpu.a = 0x90;
+        run(&mut cpu, &mut memory, 3);
+        assert_eq!((cpu.a, cpu.f), (0x10, FLAG_C));
+
+        // 0x20 - 0x01 = 0x19 in BCD
+        let (mut cpu, mut memory) = setup(&[0xD6, 0x01, 0x27]);
+        cpu.a = 0x20;
+        run(&mut cpu, &mut memory, 3);
+        assert_eq!((cpu.a, cpu.f), (0x19, FLAG_N));
+    }
+
+    #[test]
+    pub fn test_cpu_logic_and_rotates() {
+        let (mut cpu, mut memory) = setup(&[
+            0xE6, 0xF0, // AND 0xF0
+            0xEE, 0xFF, // XOR 0xFF
+            0xF6, 0x00, // OR 0x00
+            0x07, // RLCA
+            0x1F, // RRA
+            0x2F, // CPL
+            0x37, // SCF
+            0x3F, // CCF
+        ]);
+        cpu.a = 0x8F;
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x80, FLAG_H));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x7F, 0));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x7F, 0));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0xFE, 0));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x7F, 0));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x80, FLAG_N | FLAG_H));
+        cpu.step(&mut memory);
+        assert_eq!(cpu.f, FLAG_C);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.f, 0);
+
+        // The accumulator rotates always clear Z
+        let (mut cpu, mut memory) = setup(&[0x17]);
+        cpu.a = 0x80;
+        cpu.step(&mut memory);
+        assert_eq!((cpu.a, cpu.f), (0x00, FLAG_C));
+    }
+
+    #[test]
+    pub fn test_cpu_cb_opcodes() {
+        let (mut cpu, mut memory) = setup(&[
+            0xCB, 0x37, // SWAP A
+            0xCB, 0x7F, // BIT 7,A
+            0xCB, 0x46, // BIT 0,(HL)
+            0xCB, 0xC6, // SET 0,(HL)
+            0xCB, 0xBE, // RES 7,(HL)
+            0xCB, 0x3E, // SRL (HL)
+            0xCB, 0x28, // SRA B
+            0xCB, 0x11, // RL C
+        ]);
+        cpu.a = 0x1F;
+        cpu.b = 0x81;
+        cpu.set_hl(0xD000);
+        memory.write_byte(0xD000, 0x80);
+        assert_eq!(cpu.step(&mut memory), 2);
+        assert_eq!((cpu.a, cpu.f), (0xF1, 0));
+        assert_eq!(cpu.step(&mut memory), 2);
+        assert_eq!(cpu.f, FLAG_H);
+        assert_eq!(cpu.step(&mut memory), 3);
+        assert_eq!(cpu.f, FLAG_Z | FLAG_H);
+        assert_eq!(cpu.step(&mut memory), 4);
+        assert_eq!(memory.read_byte(0xD000), 0x81);
+        assert_eq!(cpu.step(&mut memory), 4);
+        assert_eq!(memory.read_byte(0xD000), 0x01);
+        assert_eq!(cpu.step(&mut memory), 4);
+        assert_eq!((memory.read_byte(0xD000), cpu.f), (0x00, FLAG_Z | FLAG_C));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.b, cpu.f), (0xC0, FLAG_C));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.c, cpu.f), (0x01, 0));
+    }
+
+    #[test]
+    pub fn test_cpu_jumps_and_calls() {
+        let program = [
+            0xCD, 0x10, 0xC0, // 0xC000: CALL 0xC010
+            0x20, 0x02, // 0xC003: JR NZ,+2
+            0x18, 0x03, // 0xC005: JR +3
+            0xC3, 0x20, 0xC0, // 0xC007: JP 0xC020
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // padding
+            0xAF, // 0xC010: XOR A
+            0xC0, // 0xC011: RET NZ
+            0xC9, // 0xC012: RET
+        ];
+        let (mut cpu, mut memory) = setup(&program);
+        assert_eq!(cpu.step(&mut memory), 6);
+        assert_eq!((cpu.pc, cpu.sp), (0xC010, 0xFFFC));
+        assert_eq!(memory.read_word(0xFFFC), 0xC003);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.step(&mut memory), 2);
+        assert_eq!(cpu.step(&mut memory), 4);
+        assert_eq!((cpu.pc, cpu.sp), (0xC003, 0xFFFE));
+        assert_eq!(cpu.step(&mut memory), 2);
+        assert_eq!(cpu.pc, 0xC005);
+        assert_eq!(cpu.step(&mut memory), 3);
+        assert_eq!(cpu.pc, 0xC00A);
+
+        // Conditional calls and jumps take fewer cycles when not taken
+        let (mut cpu, mut memory) = setup(&[0xC4, 0x00, 0xD0, 0xCA, 0x00, 0xD0, 0xD8, 0xFF]);
+        cpu.f = FLAG_Z;
+        assert_eq!(cpu.step(&mut memory), 3);
+        assert_eq!(cpu.step(&mut memory), 4);
+        assert_eq!(cpu.pc, 0xD000);
+
+        // RST pushes the address of the next instruction
+        let (mut cpu, mut memory) = setup(&[0xEF]);
+        assert_eq!(cpu.step(&mut memory), 4);
+        assert_eq!((cpu.pc, memory.read_word(cpu.sp)), (0x0028, 0xC001));
+    }
+
+    #[test]
+    pub fn test_cpu_illegal_opcode() {
+        // Illegal opcodes hang the CPU
+        let (mut cpu, mut memory) = setup(&[0xD3, 0x00]);
+        cpu.step(&mut memory);
+        assert!(cpu.locked);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.pc, 0xC001);
+    }
+
+    #[test]
+    pub fn test_interrupt_dispatch() {
+        let (mut cpu, mut memory) = setup(&[0xFB, 0x00, 0x00, 0x00]);
+        memory.write_byte(0xFFFF, 0x1F);
+        memory.trigger_interrupt(Interrupt::Timer);
+        memory.trigger_interrupt(Interrupt::Serial);
+
+        // EI only takes effect after the next instruction
+        cpu.step(&mut memory);
+        assert!(!cpu.ime);
+        cpu.step(&mut memory);
+        assert!(cpu.ime);
+        assert_eq!(cpu.pc, 0xC002);
+
+        // The highest priority interrupt is dispatched in 5 M-cycles and its IF bit cleared
+        assert_eq!(cpu.step(&mut memory), 5);
+        assert_eq!((cpu.pc, cpu.sp, cpu.ime), (0x0050, 0xFFFC, false));
+        assert_eq!(memory.read_word(0xFFFC), 0xC002);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE8);
+
+        // DI straight after EI keeps interrupts off
+        let (mut cpu, mut memory) = setup(&[0xFB, 0xF3, 0x00]);
+        memory.write_byte(0xFFFF, 0x01);
+        memory.trigger_interrupt(Interrupt::VBlank);
+        run(&mut cpu, &mut memory, 3);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.pc, 0xC003);
+    }
+
+    #[test]
+    pub fn test_interrupt_cancelled_by_ie_push() {
+        // With SP at 0x0000 pushing the high byte of PC overwrites IE and cancels the dispatch
+        let (mut cpu, mut memory) = setup(&[0x00]);
+        memory.write_byte(0xFFFF, 0x01);
+        memory.trigger_interrupt(Interrupt::VBlank);
+        cpu.ime = true;
+        cpu.sp = 0x0000;
+        assert_eq!(cpu.step(&mut memory), 5);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE1);
+
+        // Pushing a high byte that keeps the interrupt enabled dispatches it as usual
+        let (mut cpu, mut memory) = setup(&[0x00]);
+        memory.write_byte(0xFFFF, 0x01);
+        memory.trigger_interrupt(Interrupt::VBlank);
+        cpu.ime = true;
+        cpu.sp = 0x0000;
+        cpu.pc = 0xC100;
+        memory.write_byte(0xC100, 0x00);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.pc, 0x0040);
+    }
+
+    #[test]
+    pub fn test_halt() {
+        let (mut cpu, mut memory) = setup(&[0x76, 0x3C]);
+        memory.write_byte(0xFFFF, 0x04);
+        cpu.step(&mut memory);
+        assert!(cpu.halted);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.pc, 0xC001);
+
+        // With IME off a pending interrupt wakes the CPU without dispatching it
+        memory.trigger_interrupt(Interrupt::Timer);
+        cpu.step(&mut memory);
+        assert!(!cpu.halted);
+        assert_eq!((cpu.pc, cpu.a), (0xC002, 0x01));
+        assert_eq!(memory.read_byte(0xFF0F), 0xE4);
+
+        // With IME on it is dispatched with the address after HALT pushed
+        let (mut cpu, mut memory) = setup(&[0x76, 0x00]);
+        memory.write_byte(0xFFFF, 0x04);
+        cpu.ime = true;
+        cpu.step(&mut memory);
+        memory.trigger_interrupt(Interrupt::Timer);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(memory.read_word(cpu.sp), 0xC001);
+    }
+
+    #[test]
+    pub fn test_halt_bug() {
+        // HALT with IME off and an interrupt pending does not halt, the next byte is read twice
+        let (mut cpu, mut memory) = setup(&[0x76, 0x3C, 0x00]);
+        memory.write_byte(0xFFFF, 0x04);
+        memory.trigger_interrupt(Interrupt::Timer);
+        cpu.step(&mut memory);
+        assert!(!cpu.halted);
+        cpu.step(&mut memory);
+        assert_eq!((cpu.pc, cpu.a), (0xC001, 0x01));
+        cpu.step(&mut memory);
+        assert_eq!((cpu.pc, cpu.a), (0xC002, 0x02));
+    }
+
+    #[test]
+    pub fn test_cpu_access_timing() {
+        // TIMA counts every 4 M-cycles, the read in the third M-cycle of LDH A,(n) sees the increment
+        // that happens 4 M-cycles after the timer starts
+        let (mut cpu, mut memory) = setup(&[0x00, 0xF0, 0x05, 0xF0, 0x05, 0x00]);
+        memory.write_byte(0xFF07, 0x05);
+        memory.write_byte(0xFF04, 0x00);
+        cpu.step(&mut memory);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.a, 0x01);
+        assert_eq!(memory.read_byte(0xFF05), 0x01);
+
+        // The second read lands on the 7th M-cycle, one before the next increment
+        cpu.step(&mut memory);
+        assert_eq!(cpu.a, 0x01);
+        cpu.step(&mut memory);
+        assert_eq!(memory.read_byte(0xFF05), 0x02);
+
+        // An interrupt raised in the middle of an instruction is taken after it
+        let (mut cpu, mut memory) = setup(&[0x00, 0x00, 0x00, 0x00, 0x00]);
+        memory.write_byte(0xFF07, 0x05);
+        memory.write_byte(0xFF06, 0xFE);
+        memory.write_byte(0xFF05, 0xFF);
+        memory.write_byte(0xFF04, 0x00);
+        memory.write_byte(0xFFFF, 0x04);
+        cpu.ime = true;
+        // TIMA overflows at the end of the 4th M-cycle and is reloaded at the end of the 5th
+        for _ in 0..4 {
+            cpu.step(&mut memory);
+        }
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        cpu.step(&mut memory);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE4);
+        assert_eq!(cpu.pc, 0xC005);
+        cpu.step(&mut memory);
+        assert_eq!(cpu.pc, 0x0050);
+    }
+}
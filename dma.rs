@@ -0,0 +1,143 @@
+// Number of bytes copied by an OAM DMA transfer, one per M-cycle
+pub const OAM_DMA_LENGTH: u16 = 0xA0;
+
+// OAM DMA transfer started by writing the source page to 0xFF46
+pub struct OamDma {
+    // Source address of the transfer currently copying
+    source: u16,
+    // Index of the next byte to copy, OAM_DMA_LENGTH when no transfer is copying
+    index: u16,
+    // Transfer waiting for its setup M-cycle, as (source address, M-cycles left)
+    pending: Option<(u16, u8)>,
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma { source: 0, index: OAM_DMA_LENGTH, pending: None }
+    }
+
+    // Start a transfer from the given source page, it begins copying after one M-cycle of setup.
+    // A transfer already running keeps copying until the new one takes over
+    pub fn start(&mut self, page: u8) {
+        // Pages past work RAM read from the echo RAM mirror
+        let source = if page >= 0xE0 { ((page as u16) << 8) - 0x2000 } else { (page as u16) << 8 };
+        self.pending = Some((source, 1));
+    }
+
+    // True while a transfer is copying and owns the bus it reads from
+    pub fn is_active(&self) -> bool {
+        self.index < OAM_DMA_LENGTH
+    }
+
+    // True while a transfer is copying or waiting to start
+    pub fn is_running(&self) -> bool {
+        self.is_active() || self.pending.is_some()
+    }
+
+    // Source address and OAM index of the byte copied in the current M-cycle
+    pub fn current(&self) -> Option<(u16, usize)> {
+        if self.is_active() {
+            Some((self.source + self.index, self.index as usize))
+        } else {
+            None
+        }
+    }
+
+    // Move on to the next M-cycle, after the current byte was copied
+    pub fn advance(&mut self) {
+        if self.is_active() {
+            self.index += 1;
+        }
+        if let Some((source, delay)) = self.pending {
+            if delay > 1 {
+                self.pending = Some((source, delay - 1));
+            } else {
+                self.source = source;
+                self.index = 0;
+                self.pending = None;
+            }
+        }
+    }
+}
+
+// Bytes copied by each block of a CGB VRAM DMA transfer
+pub const HDMA_BLOCK_SIZE: u16 = 0x10;
+// Clock cycles the CPU is halted for while a block is copied at normal speed, two bytes per M-cycle
+pub const HDMA_BLOCK_CYCLES: u32 = 32;
+
+// CGB VRAM DMA (0xFF51-0xFF55), copies 16-byte blocks into video RAM either all at once
+// (general purpose) or one block per H-Blank
+pub struct Hdma {
+    // Source address, the low 4 bits are always 0
+    source: u16,
+    // Destination offset into video RAM, the low 4 bits are always 0
+    dest: u16,
+    // Number of blocks left to copy minus 1, as read back from HDMA5
+    length: u8,
+    // True while a transfer is copying
+    active: bool,
+    // True when the transfer copies one block per H-Blank rather than all at once
+    hblank: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Hdma {
+        Hdma { source: 0, dest: 0, length: 0x7F, active: false, hblank: false }
+    }
+
+    // Read a register, only HDMA5 can be read back
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            // Bit 7 is clear while a transfer is running, the low bits count the blocks left minus 1
+            0xFF55 => ((!self.active as u8) << 7) | self.length,
+            _ => 0xFF,
+        }
+    }
+
+    // Write a register. Writing HDMA5 starts a transfer, or cancels a running H-Blank transfer when bit 7 is clear
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF51 => self.source = (self.source & 0x00FF) | ((val as u16) << 8),
+            0xFF52 => self.source = (self.source & 0xFF00) | (val as u16 & 0xF0),
+            0xFF53 => self.dest = (self.dest & 0x00FF) | ((val as u16 & 0x1F) << 8),
+            0xFF54 => self.dest = (self.dest & 0xFF00) | (val as u16 & 0xF0),
+            0xFF55 => {
+                if self.active && self.hblank && val & 0x80 == 0 {
+                    self.active = false;
+                    return;
+                }
+                self.length = val & 0x7F;
+                self.active = true;
+                self.hblank = val & 0x80 != 0;
+            },
+            _ => (),
+        }
+    }
+
+    // True while a transfer is copying
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // True when the running transfer waits for H-Blank between blocks
+    pub fn is_hblank(&self) -> bool {
+        self.hblank
+    }
+
+    // Source address and video RAM offset of the next block to copy, moving the transfer past it.
+    // None when no transfer is running
+    pub fn next_block(&mut self) -> Option<(u16, u16)> {
+        if !self.active {
+            return None;
+        }
+        let block = (self.source, self.dest);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_SIZE);
+        self.dest = (self.dest + HDMA_BLOCK_SIZE) & 0x1FF0;
+        // Counting down past 0 leaves 0x7F, so a finished transfer reads back as 0xFF
+        self.length = self.length.wrapping_sub(1) & 0x7F;
+        if self.length == 0x7F {
+            self.active = false;
+        }
+        Some(block)
+    }
+}
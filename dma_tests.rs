@@ -0,0 +1,137 @@
+use crate::boot::Model;
+use crate::cartridge_tests::build_rom;
+use crate::memory::Memory;
+
+#[cfg(test)]
+pub mod tests {
+    use super::*; // Import the functions and types from the parent module
+
+    // Memory with a cartridge and 160 bytes of known data in work RAM at 0xC100
+    fn setup() -> Memory {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x00, 0x00, 0x00)).unwrap();
+        for i in 0..0xA0 {
+            memory.write_byte(0xC100 + i, i as u8 ^ 0x5A);
+        }
+        memory
+    }
+
+    #[test]
+    pub fn test_oam_dma_timing() {
+        let mut memory = setup();
+        memory.write_byte(0xFF46, 0xC1);
+
+        // One setup M-cycle, then one byte per M-cycle for 160 M-cycles. OAM reads 0xFF while it is written
+        assert_eq!(memory.read_byte(0xFE00), 0x00);
+        memory.step(4);
+        assert_eq!(memory.read_byte(0xFE00), 0xFF);
+        memory.step(4 * 159);
+        assert_eq!(memory.read_byte(0xFE00), 0xFF);
+        memory.step(4);
+        for i in 0..0xA0 {
+            assert_eq!(memory.read_byte(0xFE00 + i), i as u8 ^ 0x5A);
+        }
+    }
+
+    #[test]
+    pub fn test_oam_dma_bus_conflicts() {
+        let mut memory = setup();
+        memory.write_byte(0xFF80, 0x11);
+        memory.write_byte(0x8000, 0x22);
+        memory.write_byte(0xFF46, 0xC1);
+        memory.step(4 * 3);
+
+        // HRAM and I/O registers stay accessible
+        assert_eq!(memory.read_byte(0xFF80), 0x11);
+        assert_eq!(memory.read_byte(0xFF46), 0xC1);
+
+        // The transfer reads work RAM, so the cartridge bus returns the byte being copied
+        assert_eq!(memory.read_byte(0x0000), 0x02 ^ 0x5A);
+        assert_eq!(memory.read_byte(0xC000), 0x02 ^ 0x5A);
+
+        // Video RAM is on its own bus and is not affected
+        assert_eq!(memory.read_byte(0x8000), 0x22);
+
+        // Writes to the busy bus are lost
+        memory.write_byte(0xC000, 0x33);
+        memory.step(4 * 160);
+        assert_eq!(memory.read_byte(0xC000), 0x00);
+    }
+
+    #[test]
+    pub fn test_oam_dma_sources() {
+        // Transfers from cartridge ROM
+        let mut memory = setup();
+        memory.write_byte(0xFF46, 0x40);
+        memory.step(4 * 161);
+        assert_eq!(memory.read_byte(0xFE00), 0x01);
+
+        // Pages from 0xE0 read the work RAM mirror
+        memory.write_byte(0xFF46, 0xE1);
+        memory.step(4 * 161);
+        assert_eq!(memory.read_byte(0xFE9F), 0x9F ^ 0x5A);
+    }
+
+    // CGB memory with a cartridge and the HDMA registers set to copy from 0xC100 to 0x8800
+    fn setup_hdma() -> Memory {
+        let mut memory = setup();
+        memory.set_model(Model::Cgb);
+        memory.write_byte(0xFF51, 0xC1);
+        memory.write_byte(0xFF52, 0x0F);
+        memory.write_byte(0xFF53, 0xE8);
+        memory.write_byte(0xFF54, 0x0F);
+        memory
+    }
+
+    #[test]
+    pub fn test_general_purpose_hdma() {
+        let mut memory = setup_hdma();
+        memory.write_byte(0xFF4F, 0x01);
+
+        // Four blocks are copied at once into the selected bank and the CPU is halted for them
+        memory.write_byte(0xFF55, 0x03);
+        assert_eq!(memory.read_byte(0xFF55), 0xFF);
+        assert_eq!(memory.take_dma_stall(), 4 * 32);
+        assert_eq!(memory.take_dma_stall(), 0);
+        for i in 0..0x40 {
+            assert_eq!(memory.read_byte(0x8800 + i), i as u8 ^ 0x5A);
+        }
+        assert_eq!(memory.read_byte(0x8840), 0x00);
+        memory.write_byte(0xFF4F, 0x00);
+        assert_eq!(memory.read_byte(0x8800), 0x00);
+    }
+
+    #[test]
+    pub fn test_hblank_hdma() {
+        let mut memory = setup_hdma();
+        memory.write_byte(0xFF40, 0x80);
+        memory.ppu_mut().set_mode(3);
+
+        // Nothing is copied until H-Blank, the remaining length reads back with bit 7 clear
+        memory.write_byte(0xFF55, 0x82);
+        assert_eq!(memory.read_byte(0xFF55), 0x02);
+        assert_eq!(memory.read_byte(0x8800), 0x00);
+        memory.hblank_dma();
+        assert_eq!(memory.read_byte(0xFF55), 0x01);
+        assert_eq!(memory.read_byte(0x880F), 0x0F ^ 0x5A);
+        assert_eq!(memory.read_byte(0x8810), 0x00);
+        assert_eq!(memory.take_dma_stall(), 32);
+
+        // Writing HDMA5 with bit 7 clear cancels it, the remaining length stays readable
+        memory.write_byte(0xFF55, 0x00);
+        assert_eq!(memory.read_byte(0xFF55), 0x81);
+        memory.hblank_dma();
+        assert_eq!(memory.read_byte(0x8810), 0x00);
+
+        // Started during H-Blank the first block is copied straight away
+        memory.ppu_mut().set_mode(0);
+        memory.write_byte(0xFF55, 0x81);
+        assert_eq!(memory.read_byte(0xFF55), 0x00);
+        assert_eq!(memory.read_byte(0x8810), 0x10 ^ 0x5A);
+        memory.hblank_dma();
+        assert_eq!(memory.read_byte(0xFF55), 0xFF);
+        assert_eq!(memory.read_byte(0x882F), 0x2F ^ 0x5A);
+        memory.hblank_dma();
+        assert_eq!(memory.read_byte(0x8830), 0x00);
+    }
+}
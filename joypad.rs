@@ -0,0 +1,76 @@
+// Buttons on the joypad
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // Bit of the button in the pressed mask, directions in the low nibble and actions in the high nibble
+    fn mask(&self) -> u8 {
+        match self {
+            Button::Right => 0x01,
+            Button::Left => 0x02,
+            Button::Up => 0x04,
+            Button::Down => 0x08,
+            Button::A => 0x10,
+            Button::B => 0x20,
+            Button::Select => 0x40,
+            Button::Start => 0x80,
+        }
+    }
+}
+
+// Joypad register (0xFF00)
+pub struct Joypad {
+    // Bits 4-5 of P1, a 0 selects the direction (bit 4) or action (bit 5) buttons
+    select: u8,
+    // Buttons currently held, one bit per button
+    pressed: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad { select: 0b0011_0000, pressed: 0 }
+    }
+
+    // Read P1, the button lines are active low and unused bits read as 1
+    pub fn read(&self) -> u8 {
+        0b1100_0000 | self.select | (!self.lines() & 0x0F)
+    }
+
+    // Write P1, only the select bits are writable
+    pub fn write(&mut self, val: u8) {
+        self.select = val & 0b0011_0000;
+    }
+
+    // Press a button, returns true if a selected line went low and the joypad interrupt should be requested
+    pub fn press(&mut self, button: Button) -> bool {
+        let before = self.lines();
+        self.pressed |= button.mask();
+        self.lines() & !before != 0
+    }
+
+    // Release a button
+    pub fn release(&mut self, button: Button) {
+        self.pressed &= !button.mask();
+    }
+
+    // Button lines pulled low by the selected, pressed buttons
+    fn lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & 0b0001_0000 == 0 {
+            lines |= self.pressed & 0x0F;
+        }
+        if self.select & 0b0010_0000 == 0 {
+            lines |= self.pressed >> 4;
+        }
+        lines
+    }
+}
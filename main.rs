@@ -1,5 +1,145 @@
-mod memory_tests;
+// The emulator core exposes more API than the command line frontend uses yet
+#![allow(dead_code)]
+
+mod apu;
+mod boot;
+mod cartridge;
+mod cpu;
+mod dma;
+mod joypad;
+mod mbc;
 mod memory;
+mod ppu;
+mod rtc;
+mod scheduler;
+mod serial;
+mod timer;
+
+#[cfg(test)]
+mod boot_tests;
+#[cfg(test)]
+mod cartridge_tests;
+#[cfg(test)]
+mod cpu_tests;
+#[cfg(test)]
+mod dma_tests;
+#[cfg(test)]
+mod mbc_tests;
+#[cfg(test)]
+mod memory_tests;
+#[cfg(test)]
+mod ppu_tests;
+#[cfg(test)]
+mod rtc_tests;
+#[cfg(test)]
+mod scheduler_tests;
+#[cfg(test)]
+mod timer_tests;
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+
+use boot::{BootRom, Model};
+use cartridge::Cartridge;
+use cpu::Cpu;
+use memory::Memory;
+use ppu::Renderer;
+
+fn main() {
+    // --pixel-fifo selects the slower renderer that times mode 3 dot by dot, --frames <n> exits after n frames,
+    // the rest are positional
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let pixel_fifo = args.iter().any(|arg| arg == "--pixel-fifo");
+    args.retain(|arg| arg != "--pixel-fifo");
+    let mut frames = None;
+    if let Some(index) = args.iter().position(|arg| arg == "--frames") {
+        match args.get(index + 1).and_then(|count| count.parse::<u64>().ok()) {
+            Some(count) => frames = Some(count),
+            None => {
+                eprintln!("--frames needs a frame count");
+                process::exit(1);
+            }
+        }
+        args.drain(index..index + 2);
+    }
+
+    let path = match args.first() {
+        Some(path) => path.clone(),
+        None => {
+            eprintln!("Usage: GameboyEmulator [--pixel-fifo] [--frames <n>] <rom file> [boot rom file]");
+            process::exit(1);
+        }
+    };
+
+    let cartridge = match Cartridge::from_file(&path) {
+        Ok(cartridge) => cartridge,
+        Err(err) => {
+            eprintln!("Failed to load {}: {}", path, err);
+            process::exit(1);
+        }
+    };
+    println!("Loaded {} ({} banks)", cartridge.header().title, cartridge.rom_banks());
+
+    let mut memory = Memory::with_renderer(if pixel_fifo { Renderer::PixelFifo } else { Renderer::Scanline });
+    if let Err(err) = memory.load_cartridge(cartridge) {
+        eprintln!("Failed to load {}: {}", path, err);
+        process::exit(1);
+    }
+
+    // Run the boot ROM if one was given, otherwise start the game straight away
+    let mut cpu = match args.get(1) {
+        Some(boot_path) => match BootRom::from_file(boot_path) {
+            Ok(boot_rom) => {
+                if boot_rom.is_cgb() {
+                    memory.set_model(Model::Cgb);
+                }
+                memory.load_boot_rom(boot_rom);
+                Cpu::new()
+            },
+            Err(err) => {
+                eprintln!("Failed to load {}: {}", boot_path, err);
+                process::exit(1);
+            }
+        },
+        None => {
+            // Without a boot ROM to go by, CGB games get a CGB so their colour and banking registers work
+            if memory.cartridge().header().supports_cgb() {
+                memory.set_model(Model::Cgb);
+            }
+            Cpu::from_registers(memory.skip_boot())
+        },
+    };
+
+    run(&mut cpu, &mut memory, frames);
+}
+
+// Without a display yet, run headless and echo the serial port output, which is where test ROMs report results.
+// Runs forever unless a number of frames is given. Battery-backed RAM is saved at the end of each frame it
+// changed in, since stopping the emulator with Ctrl-C skips the save Memory makes when it is dropped
+fn run(cpu: &mut Cpu, memory: &mut Memory, frames: Option<u64>) {
+    let mut printed = 0;
+    let mut frame = 0;
+    while frames != Some(frame) {
+        cpu.step(memory);
+        let stall = memory.take_dma_stall();
+        memory.step(stall);
+        let output = memory.serial_output();
+        if output.len() > printed {
+            print!("{}", String::from_utf8_lossy(&output[printed..]));
+            io::stdout().flush().ok();
+            printed = output.len();
+        }
+        if memory.ppu_mut().take_frame() {
+            frame += 1;
+            if memory.save_pending() {
+                if let Err(err) = memory.flush_save() {
+                    eprintln!("Failed to write save file: {}", err);
+                }
+            }
+        }
+    }
+}
 
 #[test]
 fn run_tests() {
@@ -7,4 +147,90 @@ fn run_tests() {
     memory_tests::tests::test_banked_memory();
     memory_tests::tests::test_io_registers();
     memory_tests::tests::test_read_write_word();
-}
\ No newline at end of file
+    memory_tests::tests::test_rom_is_read_only();
+    memory_tests::tests::test_load_rom_too_large();
+    memory_tests::tests::test_mbc_selected_from_header();
+    memory_tests::tests::test_save_ram();
+    memory_tests::tests::test_save_file();
+    memory_tests::tests::test_main_loop_saves();
+    memory_tests::tests::test_save_ram_with_clock();
+    memory_tests::tests::test_echo_ram();
+    memory_tests::tests::test_oam_access();
+    memory_tests::tests::test_unusable_area();
+    memory_tests::tests::test_hram();
+    memory_tests::tests::test_io_read_masks();
+    memory_tests::tests::test_io_write_side_effects();
+    memory_tests::tests::test_joypad();
+    memory_tests::tests::test_serial_transfer();
+    memory_tests::tests::test_interrupt_flags();
+    memory_tests::tests::test_component_interrupts();
+    memory_tests::tests::test_cgb_vram_banking();
+    memory_tests::tests::test_cgb_wram_banking();
+    memory_tests::tests::test_double_speed();
+    cartridge_tests::tests::test_parse_header();
+    cartridge_tests::tests::test_parse_cgb_header();
+    cartridge_tests::tests::test_invalid_headers();
+    mbc_tests::tests::test_mbc1_rom_banking();
+    mbc_tests::tests::test_mbc1_large_rom();
+    mbc_tests::tests::test_mbc1_ram();
+    mbc_tests::tests::test_mbc1_multicart();
+    mbc_tests::tests::test_mbc2_registers();
+    mbc_tests::tests::test_mbc2_ram();
+    mbc_tests::tests::test_mbc3_banking();
+    mbc_tests::tests::test_mbc3_rtc();
+    mbc_tests::tests::test_mbc5_banking();
+    mbc_tests::tests::test_mbc5_rumble();
+    rtc_tests::tests::test_rtc_counts_cycles();
+    rtc_tests::tests::test_rtc_latch();
+    rtc_tests::tests::test_rtc_day_carry_and_halt();
+    rtc_tests::tests::test_rtc_out_of_range_values();
+    rtc_tests::tests::test_rtc_footer();
+    boot_tests::tests::test_boot_rom_overlay();
+    boot_tests::tests::test_cgb_boot_rom_overlay();
+    boot_tests::tests::test_skip_boot();
+    boot_tests::tests::test_skip_boot_runs_entry_point();
+    boot_tests::tests::test_skip_boot_cgb_game();
+    dma_tests::tests::test_oam_dma_timing();
+    dma_tests::tests::test_oam_dma_bus_conflicts();
+    dma_tests::tests::test_oam_dma_sources();
+    dma_tests::tests::test_general_purpose_hdma();
+    dma_tests::tests::test_hblank_hdma();
+    cpu_tests::tests::test_cpu_loads();
+    cpu_tests::tests::test_cpu_stack();
+    cpu_tests::tests::test_cpu_arithmetic();
+    cpu_tests::tests::test_cpu_daa();
+    cpu_tests::tests::test_cpu_logic_and_rotates();
+    cpu_tests::tests::test_cpu_cb_opcodes();
+    cpu_tests::tests::test_cpu_jumps_and_calls();
+    cpu_tests::tests::test_cpu_illegal_opcode();
+    cpu_tests::tests::test_interrupt_dispatch();
+    cpu_tests::tests::test_interrupt_cancelled_by_ie_push();
+    cpu_tests::tests::test_halt();
+    cpu_tests::tests::test_halt_bug();
+    cpu_tests::tests::test_cpu_access_timing();
+    scheduler_tests::tests::test_scheduler_order();
+    scheduler_tests::tests::test_scheduler_reschedule();
+    scheduler_tests::tests::test_register_writes_reschedule();
+    scheduler_tests::tests::test_halted_cpu_skips_to_next_event();
+    scheduler_tests::tests::test_frame_sequencer_and_clock_events();
+    timer_tests::tests::test_timer_counts_falling_edges();
+    timer_tests::tests::test_timer_div_write_glitch();
+    timer_tests::tests::test_timer_tac_glitch();
+    timer_tests::tests::test_timer_overflow_reload();
+    timer_tests::tests::test_timer_writes_during_reload();
+    ppu_tests::tests::test_ppu_mode_timing();
+    ppu_tests::tests::test_ppu_stat_interrupts();
+    ppu_tests::tests::test_ppu_background();
+    ppu_tests::tests::test_ppu_window();
+    ppu_tests::tests::test_window_wy_latch();
+    ppu_tests::tests::test_ppu_sprites();
+    ppu_tests::tests::test_fifo_drawing_length();
+    ppu_tests::tests::test_fifo_mid_line_writes();
+    ppu_tests::tests::test_stat_interrupt_blocking();
+    ppu_tests::tests::test_cgb_palette_ram();
+    ppu_tests::tests::test_cgb_background_attributes();
+    ppu_tests::tests::test_cgb_object_priority();
+    ppu_tests::tests::test_cgb_compat_mode();
+    ppu_tests::tests::test_lcd_switching();
+    ppu_tests::tests::test_stat_register_writes();
+}
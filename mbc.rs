@@ -0,0 +1,362 @@
+use crate::cartridge::{Cartridge, CartridgeError, ROM_BANK_SIZE};
+use crate::rtc::{Rtc, RtcClock};
+
+// Size of a single switchable external RAM bank
+pub const RAM_BANK_SIZE: usize = 0x2000;
+// MBC2 has 512 half-bytes of RAM built into the controller
+pub const MBC2_RAM_SIZE: usize = 0x200;
+
+// MBC (Memory Bank Controller) enum
+#[allow(clippy::upper_case_acronyms)]
+pub enum MBC {
+    // ROM only, optionally with up to 8KB of RAM
+    None {
+        ram: Vec<u8>,
+    },
+    MBC1 {
+        // RAM enabled by writing 0x0A to 0x0000-0x1FFF
+        ram_enable: bool,
+        // 2-bit BANK2 register (0x4000-0x5FFF), RAM bank or upper ROM bank bits
+        ram_bank: u8,
+        // 5-bit BANK1 register (0x2000-0x3FFF), lower ROM bank bits
+        rom_bank: u8,
+        // Banking mode (0x6000-0x7FFF), when set BANK2 also applies to 0x0000-0x3FFF and RAM
+        banking_mode: bool,
+        // MBC1M multicart wiring, BANK2 is shifted by 4 instead of 5
+        multicart: bool,
+        // External RAM
+        ram: Vec<u8>,
+    },
+    MBC2 {
+        // RAM enabled by writing 0x0A with address bit 8 clear
+        ram_enable: bool,
+        // 4-bit ROM bank register, written with address bit 8 set
+        rom_bank: u8,
+        // Built-in 512x4-bit RAM, only the low nibble of each byte is stored
+        ram: Vec<u8>,
+    },
+    MBC3 {
+        // RAM and clock registers enabled by writing 0x0A to 0x0000-0x1FFF
+        ram_enable: bool,
+        // 7-bit ROM bank register (0x2000-0x3FFF)
+        rom_bank: u8,
+        // RAM bank (0x00-0x03) or clock register (0x08-0x0C) mapped at 0xA000-0xBFFF
+        ram_bank: u8,
+        // External RAM, up to four 8KB banks
+        ram: Vec<u8>,
+        // Real-time clock, only on MBC3+TIMER cartridges
+        rtc: Option<Rtc>,
+    },
+    MBC5 {
+        // RAM enabled by writing 0x0A to 0x0000-0x1FFF
+        ram_enable: bool,
+        // 9-bit ROM bank register, low 8 bits at 0x2000-0x2FFF and bit 8 at 0x3000-0x3FFF
+        rom_bank: u16,
+        // 4-bit RAM bank register (0x4000-0x5FFF), bit 3 drives the motor on rumble cartridges
+        ram_bank: u8,
+        // External RAM, up to sixteen 8KB banks
+        ram: Vec<u8>,
+        // Cartridge has a rumble motor wired to RAM bank bit 3
+        has_rumble: bool,
+        // Motor currently switched on
+        rumble: bool,
+    },
+    // Add other MBC variants here
+}
+
+impl MBC {
+    // Create the MBC matching the cartridge type byte in the header
+    pub fn from_cartridge(cartridge: &Cartridge, rtc_clock: RtcClock) -> Result<MBC, CartridgeError> {
+        let header = cartridge.header();
+        let ram = vec![0; header.ram_size];
+        match header.cartridge_type {
+            // ROM only, optionally with RAM and battery
+            0x00 | 0x08 | 0x09 => Ok(MBC::None { ram }),
+            0x01..=0x03 => Ok(MBC::MBC1 {
+                ram_enable: false,
+                ram_bank: 0,
+                rom_bank: 0,
+                banking_mode: false,
+                multicart: is_mbc1_multicart(cartridge),
+                ram,
+            }),
+            0x05 | 0x06 => Ok(MBC::MBC2 { ram_enable: false, rom_bank: 1, ram: vec![0; MBC2_RAM_SIZE] }),
+            0x0F..=0x13 => Ok(MBC::MBC3 {
+                ram_enable: false,
+                rom_bank: 1,
+                ram_bank: 0,
+                ram,
+                rtc: if header.has_timer() { Some(Rtc::new(rtc_clock)) } else { None },
+            }),
+            0x19..=0x1E => Ok(MBC::MBC5 {
+                ram_enable: false,
+                rom_bank: 1,
+                ram_bank: 0,
+                ram,
+                has_rumble: header.has_rumble(),
+                rumble: false,
+            }),
+            other => Err(CartridgeError::UnsupportedCartridgeType(other)),
+        }
+    }
+
+    // Get the bank number mapped at 0x0000-0x3FFF
+    pub fn get_zero_bank(&self) -> usize {
+        match self {
+            MBC::MBC1 { ram_bank, banking_mode: true, multicart, .. } => (*ram_bank as usize) << mbc1_bank2_shift(*multicart),
+            _ => 0,
+        }
+    }
+
+    // Get the bank number mapped at 0x4000-0x7FFF
+    pub fn get_bank(&self) -> usize {
+        match self {
+            // Without an MBC the second 16KB of the ROM is always mapped
+            MBC::None { .. } => 1,
+            MBC::MBC1 { ram_bank, rom_bank, multicart, .. } => {
+                // The zero check looks at all 5 bits even when the multicart wiring ignores bit 4
+                let low = if *rom_bank & 0b0001_1111 == 0 { 1 } else { *rom_bank & 0b0001_1111 };
+                let shift = mbc1_bank2_shift(*multicart);
+                ((*ram_bank as usize) << shift) | (low as usize & ((1 << shift) - 1))
+            },
+            MBC::MBC2 { rom_bank, .. } | MBC::MBC3 { rom_bank, .. } => *rom_bank as usize,
+            // Bank 0 can be mapped into the switchable window on MBC5
+            MBC::MBC5 { rom_bank, .. } => *rom_bank as usize,
+            // Add other MBC variants here
+        }
+    }
+
+    // Write to the MBC registers at 0x0000-0x7FFF
+    pub fn set_bank(&mut self, addr: u16, val: u8) {
+        match self {
+            MBC::None { .. } => (),
+            MBC::MBC1 { ram_enable, ram_bank, rom_bank, banking_mode, .. } => {
+                if addr < 0x2000 {
+                    // Enable/disable RAM
+                    *ram_enable = val & 0b0000_1111 == 0b0000_1010;
+                } else if addr < 0x4000 {
+                    // Set low 5 bits of ROM bank number
+                    *rom_bank = val & 0b0001_1111;
+                } else if addr < 0x6000 {
+                    // Set RAM bank number, or upper ROM bank bits on large cartridges
+                    *ram_bank = val & 0b0000_0011;
+                } else {
+                    // Select banking mode
+                    *banking_mode = val & 0b0000_0001 == 0b0000_0001;
+                }
+            },
+            MBC::MBC2 { ram_enable, rom_bank, .. } => {
+                if addr >= 0x4000 {
+                    // Only 0x0000-0x3FFF is decoded
+                } else if addr & 0x0100 == 0 {
+                    // Address bit 8 clear: enable/disable RAM
+                    *ram_enable = val & 0b0000_1111 == 0b0000_1010;
+                } else {
+                    // Address bit 8 set: select ROM bank, bank 0 maps to bank 1
+                    *rom_bank = if val & 0b0000_1111 == 0 { 1 } else { val & 0b0000_1111 };
+                }
+            },
+            MBC::MBC3 { ram_enable, rom_bank, ram_bank, rtc, .. } => {
+                if addr < 0x2000 {
+                    // Enable/disable RAM and clock registers
+                    *ram_enable = val & 0b0000_1111 == 0b0000_1010;
+                } else if addr < 0x4000 {
+                    // Set 7-bit ROM bank number, bank 0 maps to bank 1
+                    *rom_bank = if val & 0b0111_1111 == 0 { 1 } else { val & 0b0111_1111 };
+                } else if addr < 0x6000 {
+                    // Select RAM bank or clock register
+                    *ram_bank = val & 0b0000_1111;
+                } else if let Some(rtc) = rtc {
+                    // Latch the clock registers
+                    rtc.write_latch(val);
+                }
+            },
+            MBC::MBC5 { ram_enable, rom_bank, ram_bank, has_rumble, rumble, .. } => {
+                if addr < 0x2000 {
+                    // Enable/disable RAM
+                    *ram_enable = val & 0b0000_1111 == 0b0000_1010;
+                } else if addr < 0x3000 {
+                    // Set lower 8 bits of ROM bank number
+                    *rom_bank = (*rom_bank & 0x0100) | val as u16;
+                } else if addr < 0x4000 {
+                    // Set upper bit of ROM bank number
+                    *rom_bank = (*rom_bank & 0x00FF) | ((val as u16 & 0x01) << 8);
+                } else if addr < 0x6000 {
+                    // Set RAM bank number, on rumble cartridges bit 3 switches the motor instead
+                    if *has_rumble {
+                        *rumble = val & 0b0000_1000 != 0;
+                        *ram_bank = val & 0b0000_0111;
+                    } else {
+                        *ram_bank = val & 0b0000_1111;
+                    }
+                }
+            },
+            // Add other MBC variants here
+        }
+    }
+
+    // Read a byte from external RAM at 0xA000-0xBFFF, disabled or missing RAM reads as 0xFF
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        match self {
+            MBC::None { ram } => read_banked_ram(ram, 0, addr),
+            MBC::MBC1 { ram_enable, ram, .. } => {
+                if !*ram_enable {
+                    return 0xFF;
+                }
+                read_banked_ram(ram, self.mbc1_ram_bank(), addr)
+            },
+            MBC::MBC2 { ram_enable, ram, .. } => {
+                if !*ram_enable {
+                    return 0xFF;
+                }
+                // Only 9 address bits are decoded so the RAM echoes across the whole window,
+                // the missing upper nibble reads as 1s
+                0xF0 | ram[addr as usize & (MBC2_RAM_SIZE - 1)]
+            },
+            MBC::MBC3 { ram_enable, ram_bank, ram, rtc, .. } => {
+                if !*ram_enable {
+                    return 0xFF;
+                }
+                match (*ram_bank, rtc) {
+                    (0x00..=0x03, _) => read_banked_ram(ram, *ram_bank as usize, addr),
+                    (0x08..=0x0C, Some(rtc)) => rtc.read(*ram_bank),
+                    _ => 0xFF,
+                }
+            },
+            MBC::MBC5 { ram_enable, ram_bank, ram, .. } => {
+                if !*ram_enable {
+                    return 0xFF;
+                }
+                read_banked_ram(ram, *ram_bank as usize, addr)
+            },
+        }
+    }
+
+    // Write a byte to external RAM at 0xA000-0xBFFF, ignored when RAM is disabled or missing. Returns true if the
+    // byte was stored, in RAM or a clock register, so the save needs writing
+    pub fn write_ram(&mut self, addr: u16, val: u8) -> bool {
+        let bank = self.mbc1_ram_bank();
+        match self {
+            MBC::None { ram } => write_banked_ram(ram, 0, addr, val),
+            MBC::MBC1 { ram_enable, ram, .. } => *ram_enable && write_banked_ram(ram, bank, addr, val),
+            MBC::MBC2 { ram_enable, ram, .. } => {
+                if *ram_enable {
+                    ram[addr as usize & (MBC2_RAM_SIZE - 1)] = val & 0x0F;
+                }
+                *ram_enable
+            },
+            MBC::MBC3 { ram_enable, ram_bank, ram, rtc, .. } => {
+                if !*ram_enable {
+                    return false;
+                }
+                match (*ram_bank, rtc) {
+                    (0x00..=0x03, _) => write_banked_ram(ram, *ram_bank as usize, addr, val),
+                    (0x08..=0x0C, Some(rtc)) => {
+                        rtc.write(*ram_bank, val);
+                        true
+                    },
+                    _ => false,
+                }
+            },
+            MBC::MBC5 { ram_enable, ram_bank, ram, .. } => *ram_enable && write_banked_ram(ram, *ram_bank as usize, addr, val),
+        }
+    }
+
+    // Contents of the cartridge RAM, the layout battery-backed saves are stored in
+    pub fn ram(&self) -> &[u8] {
+        match self {
+            MBC::None { ram } | MBC::MBC1 { ram, .. } | MBC::MBC2 { ram, .. } | MBC::MBC3 { ram, .. } | MBC::MBC5 { ram, .. } => ram,
+        }
+    }
+
+    // Mutable access to the cartridge RAM
+    fn ram_mut(&mut self) -> &mut [u8] {
+        match self {
+            MBC::None { ram } | MBC::MBC1 { ram, .. } | MBC::MBC2 { ram, .. } | MBC::MBC3 { ram, .. } | MBC::MBC5 { ram, .. } => ram,
+        }
+    }
+
+    // Replace the contents of the cartridge RAM, extra bytes are ignored and missing bytes left untouched
+    pub fn load_ram(&mut self, data: &[u8]) {
+        let nibbles = matches!(self, MBC::MBC2 { .. });
+        let ram = self.ram_mut();
+        let len = ram.len().min(data.len());
+        ram[..len].copy_from_slice(&data[..len]);
+        if nibbles {
+            // Other emulators may store the unused upper nibble
+            ram.iter_mut().for_each(|b| *b &= 0x0F);
+        }
+    }
+
+    // True while the rumble motor of the cartridge is switched on
+    pub fn rumble(&self) -> bool {
+        matches!(self, MBC::MBC5 { rumble: true, .. })
+    }
+
+    // Real-time clock of the cartridge, if it has one
+    pub fn rtc(&self) -> Option<&Rtc> {
+        match self {
+            MBC::MBC3 { rtc, .. } => rtc.as_ref(),
+            _ => None,
+        }
+    }
+
+    // Mutable access to the real-time clock of the cartridge, if it has one
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        match self {
+            MBC::MBC3 { rtc, .. } => rtc.as_mut(),
+            _ => None,
+        }
+    }
+
+    // Advance cartridge hardware that runs off the system clock
+    pub fn tick(&mut self, cycles: u32) {
+        if let Some(rtc) = self.rtc_mut() {
+            rtc.tick(cycles);
+        }
+    }
+
+    // RAM bank selected on MBC1, BANK2 only applies to RAM in banking mode 1
+    fn mbc1_ram_bank(&self) -> usize {
+        match self {
+            MBC::MBC1 { ram_bank, banking_mode: true, .. } => *ram_bank as usize,
+            _ => 0,
+        }
+    }
+}
+
+// MBC1 multicarts wire BANK2 to ROM address lines 18-19 instead of 19-20
+fn mbc1_bank2_shift(multicart: bool) -> u32 {
+    if multicart {
+        4
+    } else {
+        5
+    }
+}
+
+// MBC1M multicarts are 1MB and carry a second copy of the Nintendo logo in the header of bank 0x10,
+// the first game of the compilation
+fn is_mbc1_multicart(cartridge: &Cartridge) -> bool {
+    if cartridge.rom_size() != 64 * ROM_BANK_SIZE {
+        return false;
+    }
+    (0x0104..0x0134).all(|addr| cartridge.read_rom_bank(0x10, addr) == cartridge.read_rom(addr as usize))
+}
+
+// Read a byte from a RAM array split into 8KB banks, sizes smaller than the window are mirrored
+fn read_banked_ram(ram: &[u8], bank: usize, addr: u16) -> u8 {
+    if ram.is_empty() {
+        return 0xFF;
+    }
+    ram[(bank * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))) % ram.len()]
+}
+
+// Write a byte to a RAM array split into 8KB banks, false if there is no RAM to write to
+fn write_banked_ram(ram: &mut [u8], bank: usize, addr: u16, val: u8) -> bool {
+    if ram.is_empty() {
+        return false;
+    }
+    let len = ram.len();
+    ram[(bank * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))) % len] = val;
+    true
+}
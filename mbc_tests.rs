@@ -0,0 +1,257 @@
+use crate::cartridge_tests::{build_rom, NINTENDO_LOGO};
+use crate::mbc::MBC;
+use crate::memory::Memory;
+use crate::rtc::{RtcClock, CYCLES_PER_SECOND};
+
+#[cfg(test)]
+pub mod tests {
+    use super::*; // Import the functions and types from the parent module
+
+    #[test]
+    pub fn test_mbc1_rom_banking() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x01, 0x04, 0x00)).unwrap();
+
+        // Bank 1 is mapped at power on, and selecting bank 0 maps bank 1
+        assert_eq!(memory.read_byte(0x4000), 1);
+        memory.write_byte(0x2000, 0x00);
+        assert_eq!(memory.read_byte(0x4000), 1);
+
+        memory.write_byte(0x2000, 0x1F);
+        assert_eq!(memory.read_byte(0x7FFF), 0x1F);
+
+        // Only the low 5 bits are used, so 0x20 is treated as 0 and maps bank 1
+        memory.write_byte(0x2000, 0x20);
+        assert_eq!(memory.read_byte(0x4000), 1);
+    }
+
+    #[test]
+    pub fn test_mbc1_large_rom() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x01, 0x06, 0x00)).unwrap();
+
+        // BANK2 supplies bits 5-6 of the ROM bank
+        memory.write_byte(0x2000, 0x02);
+        memory.write_byte(0x4000, 0x01);
+        assert_eq!(memory.read_byte(0x4000), 0x22);
+
+        // In mode 0 the fixed area always maps bank 0
+        assert_eq!(memory.read_byte(0x0000), 0x00);
+
+        // In mode 1 BANK2 also applies to the fixed area
+        memory.write_byte(0x6000, 0x01);
+        assert_eq!(memory.read_byte(0x0000), 0x20);
+        assert_eq!(memory.read_byte(0x4000), 0x22);
+
+        // Bank 0x20 cannot be mapped in the switchable window, it becomes 0x21
+        memory.write_byte(0x2000, 0x00);
+        assert_eq!(memory.read_byte(0x4000), 0x21);
+    }
+
+    #[test]
+    pub fn test_mbc1_ram() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x03, 0x02, 0x03)).unwrap();
+
+        // RAM is disabled at power on
+        memory.write_byte(0xA000, 0x12);
+        assert_eq!(memory.read_byte(0xA000), 0xFF);
+
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0xA000, 0x12);
+        assert_eq!(memory.read_byte(0xA000), 0x12);
+
+        // In mode 0 the RAM bank register is ignored
+        memory.write_byte(0x4000, 0x02);
+        assert_eq!(memory.read_byte(0xA000), 0x12);
+
+        // In mode 1 it selects one of the four 8KB banks
+        memory.write_byte(0x6000, 0x01);
+        assert_eq!(memory.read_byte(0xA000), 0x00);
+        memory.write_byte(0xA000, 0x34);
+        memory.write_byte(0x4000, 0x00);
+        assert_eq!(memory.read_byte(0xA000), 0x12);
+        memory.write_byte(0x4000, 0x02);
+        assert_eq!(memory.read_byte(0xA000), 0x34);
+
+        // Any value without 0x0A in the low nibble disables RAM again
+        memory.write_byte(0x0000, 0x00);
+        assert_eq!(memory.read_byte(0xA000), 0xFF);
+    }
+
+    #[test]
+    pub fn test_mbc1_multicart() {
+        // A 1MB cartridge with a second game header in bank 0x10
+        let mut rom = build_rom(0x01, 0x05, 0x00);
+        rom[0x10 * 0x4000 + 0x0104..0x10 * 0x4000 + 0x0134].copy_from_slice(&NINTENDO_LOGO);
+
+        let mut memory = Memory::new();
+        memory.load_rom(&rom).unwrap();
+        assert!(matches!(memory.mbc(), MBC::MBC1 { multicart: true, .. }));
+
+        // BANK2 is shifted by 4 and only 4 bits of BANK1 are used
+        memory.write_byte(0x4000, 0x01);
+        memory.write_byte(0x2000, 0x02);
+        assert_eq!(memory.read_byte(0x4000), 0x12);
+
+        // Bit 4 of BANK1 still counts for the bank 0 check
+        memory.write_byte(0x2000, 0x10);
+        assert_eq!(memory.read_byte(0x4000), 0x10);
+
+        // Mode 1 maps each game's first bank into the fixed area
+        memory.write_byte(0x6000, 0x01);
+        memory.write_byte(0x4000, 0x02);
+        assert_eq!(memory.read_byte(0x0000), 0x20);
+
+        // Without the second header the same cartridge uses the normal wiring
+        memory.load_rom(&build_rom(0x01, 0x05, 0x00)).unwrap();
+        assert!(matches!(memory.mbc(), MBC::MBC1 { multicart: false, .. }));
+    }
+
+    #[test]
+    pub fn test_mbc2_registers() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x06, 0x03, 0x00)).unwrap();
+        assert_eq!(memory.read_byte(0x4000), 1);
+
+        // With address bit 8 set the write selects the ROM bank
+        memory.write_byte(0x2100, 0x05);
+        assert_eq!(memory.read_byte(0x4000), 5);
+        memory.write_byte(0x0100, 0xF3);
+        assert_eq!(memory.read_byte(0x4000), 3);
+        memory.write_byte(0x0100, 0x00);
+        assert_eq!(memory.read_byte(0x4000), 1);
+
+        // With address bit 8 clear it enables RAM, whatever the address range
+        memory.write_byte(0x2000, 0x0A);
+        memory.write_byte(0xA000, 0x07);
+        assert_eq!(memory.read_byte(0xA000), 0xF7);
+        memory.write_byte(0x3EFF, 0x00);
+        assert_eq!(memory.read_byte(0xA000), 0xFF);
+        assert_eq!(memory.read_byte(0x4000), 1);
+    }
+
+    #[test]
+    pub fn test_mbc2_ram() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x06, 0x01, 0x00)).unwrap();
+        memory.write_byte(0x0000, 0x0A);
+
+        // Only the low nibble is stored and the upper nibble reads as 1s
+        memory.write_byte(0xA000, 0xAB);
+        assert_eq!(memory.read_byte(0xA000), 0xFB);
+
+        // The 512 half-bytes echo across 0xA000-0xBFFF
+        memory.write_byte(0xA1FF, 0x04);
+        assert_eq!(memory.read_byte(0xA3FF), 0xF4);
+        assert_eq!(memory.read_byte(0xBFFF), 0xF4);
+        assert_eq!(memory.read_byte(0xA200), 0xFB);
+
+        // The saved RAM holds one nibble per byte
+        assert_eq!(memory.mbc().ram().len(), 512);
+        assert_eq!(memory.mbc().ram()[0x1FF], 0x04);
+    }
+
+    #[test]
+    pub fn test_mbc3_banking() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x13, 0x06, 0x03)).unwrap();
+
+        // 7-bit ROM bank, bank 0 maps to bank 1
+        memory.write_byte(0x2000, 0x7F);
+        assert_eq!(memory.read_byte(0x4000), 0x7F);
+        memory.write_byte(0x2000, 0x80);
+        assert_eq!(memory.read_byte(0x4000), 0x01);
+
+        // Four 8KB RAM banks
+        memory.write_byte(0x0000, 0x0A);
+        for bank in 0..4 {
+            memory.write_byte(0x4000, bank);
+            memory.write_byte(0xA000, 0x10 + bank);
+        }
+        for bank in 0..4 {
+            memory.write_byte(0x4000, bank);
+            assert_eq!(memory.read_byte(0xA000), 0x10 + bank);
+        }
+
+        // Without a timer the clock registers read as open bus
+        memory.write_byte(0x4000, 0x08);
+        assert_eq!(memory.read_byte(0xA000), 0xFF);
+    }
+
+    #[test]
+    pub fn test_mbc3_rtc() {
+        let mut memory = Memory::new();
+        memory.set_rtc_clock(RtcClock::Cycles);
+        memory.load_rom(&build_rom(0x10, 0x02, 0x03)).unwrap();
+        memory.write_byte(0x0000, 0x0A);
+
+        // Set the minutes register and let 61 seconds of emulated time pass
+        memory.write_byte(0x4000, 0x09);
+        memory.write_byte(0xA000, 30);
+        memory.step(CYCLES_PER_SECOND * 61);
+
+        // Nothing is visible until the clock is latched
+        assert_eq!(memory.read_byte(0xA000), 0);
+        memory.write_byte(0x6000, 0x00);
+        memory.write_byte(0x6000, 0x01);
+        assert_eq!(memory.read_byte(0xA000), 31);
+        memory.write_byte(0x4000, 0x08);
+        assert_eq!(memory.read_byte(0xA000), 1);
+
+        // RAM bank 0 is still reachable next to the clock registers
+        memory.write_byte(0x4000, 0x00);
+        memory.write_byte(0xA000, 0x42);
+        assert_eq!(memory.read_byte(0xA000), 0x42);
+    }
+
+    #[test]
+    pub fn test_mbc5_banking() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x1B, 0x08, 0x04)).unwrap();
+
+        // Bank 0 can be mapped in the switchable window
+        memory.write_byte(0x2000, 0x00);
+        assert_eq!(memory.read_byte(0x4000), 0x00);
+
+        // The ninth bit selects banks 0x100-0x1FF
+        memory.write_byte(0x2000, 0x23);
+        memory.write_byte(0x3000, 0x01);
+        assert_eq!(memory.read_byte(0x4000), 0x23);
+        assert!(matches!(memory.mbc(), MBC::MBC5 { rom_bank: 0x123, .. }));
+        assert_eq!(memory.mbc().get_bank(), 0x123);
+
+        // Sixteen 8KB RAM banks
+        memory.write_byte(0x0000, 0x0A);
+        for bank in 0..16 {
+            memory.write_byte(0x4000, bank);
+            memory.write_byte(0xBFFF, 0x20 + bank);
+        }
+        for bank in 0..16 {
+            memory.write_byte(0x4000, bank);
+            assert_eq!(memory.read_byte(0xBFFF), 0x20 + bank);
+        }
+    }
+
+    #[test]
+    pub fn test_mbc5_rumble() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x1E, 0x02, 0x03)).unwrap();
+        memory.write_byte(0x0000, 0x0A);
+
+        // Bit 3 of the RAM bank register drives the motor and does not select a bank
+        memory.write_byte(0x4000, 0x01);
+        memory.write_byte(0xA000, 0x55);
+        assert!(!memory.rumble());
+        memory.write_byte(0x4000, 0x09);
+        assert!(memory.rumble());
+        assert_eq!(memory.read_byte(0xA000), 0x55);
+        memory.write_byte(0x4000, 0x01);
+        assert!(!memory.rumble());
+
+        // Without a motor bit 3 is a bank bit
+        memory.load_rom(&build_rom(0x1B, 0x02, 0x04)).unwrap();
+        memory.write_byte(0x4000, 0x08);
+        assert!(!memory.rumble());
+    }
+}
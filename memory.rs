@@ -1,40 +1,536 @@
+use std::fs;
+use std::io;
+
+use crate::apu::{Apu, FRAME_SEQUENCER_PERIOD};
+use crate::boot::{post_boot_io, BootRom, Model, PostBootRegisters};
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::dma::{Hdma, OamDma, HDMA_BLOCK_CYCLES, HDMA_BLOCK_SIZE};
+use crate::joypad::{Button, Joypad};
+use crate::mbc::MBC;
+use crate::ppu::{Ppu, Renderer, DMG_COLORS, MODE_DRAWING, MODE_HBLANK, MODE_OAM_SCAN, REQUEST_STAT, REQUEST_VBLANK};
+use crate::rtc::RtcClock;
+use crate::scheduler::{Event, Scheduler};
+use crate::serial::Serial;
+use crate::timer::Timer;
+
+// Size of a video RAM bank
+pub const VRAM_BANK_SIZE: usize = 0x2000;
+// Size of a work RAM bank
+pub const WRAM_BANK_SIZE: usize = 0x1000;
+// CPU clock cycles the CPU stays stopped for while switching speed
+pub const SPEED_SWITCH_CYCLES: u32 = 8200;
+
 pub struct Memory {
-    // 64KB of memory
-    mem: [u8; 0x10000],
-    // Current bank number for banked memory
-    current_bank: u8,
-    // I/O registers
-    registers: [u8; 0x100],
+    // Hardware model being emulated
+    model: Model,
+    // Boot ROM overlaid on the cartridge until 0xFF50 is written
+    boot_rom: Option<BootRom>,
+    // Video RAM (0x8000-0x9FFF), two 8KB banks on the CGB
+    vram: [u8; VRAM_BANK_SIZE * 2],
+    // Work RAM (0xC000-0xDFFF), mirrored by echo RAM at 0xE000-0xFDFF. Eight 4KB banks on the CGB,
+    // bank 0 is fixed at 0xC000 and the others are switched in at 0xD000
+    wram: [u8; WRAM_BANK_SIZE * 8],
+    // Video RAM bank selected by VBK (0xFF4F), CGB only
+    vram_bank: u8,
+    // Work RAM bank mapped at 0xD000 selected by SVBK (0xFF70), CGB only
+    wram_bank: u8,
+    // Object attribute memory (0xFE00-0xFE9F)
+    oam: [u8; 0xA0],
+    // High RAM (0xFF80-0xFFFE)
+    hram: [u8; 0x7F],
+    // Inserted cartridge holding the full ROM image
+    cartridge: Cartridge,
+    // Joypad (0xFF00)
+    joypad: Joypad,
+    // Serial port (0xFF01-0xFF02)
+    serial: Serial,
+    // Timer and divider (0xFF04-0xFF07)
+    timer: Timer,
+    // Interrupt flag register (0xFF0F)
+    interrupt_flag: u8,
+    // Sound registers (0xFF10-0xFF3F)
+    apu: Apu,
+    // PPU and LCD registers (0xFF40-0xFF4B)
+    ppu: Ppu,
+    // Last value written to the OAM DMA register (0xFF46)
+    dma_source: u8,
     // MBC (Memory Bank Controller)
     mbc: MBC,
     // Interrupt enable register
     interrupt_enable: u8,
-    // DMA (Direct Memory Access) transfer in progress
-    dma_transfer: bool,
+    // OAM DMA (Direct Memory Access) transfer
+    oam_dma: OamDma,
+    // CGB VRAM DMA transfer (0xFF51-0xFF55)
+    hdma: Hdma,
+    // Clock cycles the CPU has to stay halted for VRAM DMA blocks copied so far
+    dma_stall: u32,
+    // KEY1 (0xFF4D) bit 7, the CGB CPU runs at 8.388608 MHz
+    double_speed: bool,
+    // KEY1 bit 0, the next STOP switches speed
+    speed_switch_armed: bool,
+    // Odd CPU clock cycle left over in double speed, not yet passed on to the hardware on the normal speed clock
+    half_cycle: u32,
+    // Cycles of the normal speed clock since power on
+    normal_cycles: u64,
+    // Value of normal_cycles the cartridge clock was last brought up to date at
+    rtc_synced: u64,
+    // Time in CPU clock cycles the timer and serial port were last brought up to date at
+    timers_synced: u64,
+    // Times of the upcoming hardware events
+    scheduler: Scheduler,
+    // Time source for cartridge real-time clocks
+    rtc_clock: RtcClock,
+    // Cartridge RAM was written since the last save
+    ram_dirty: bool,
 }
 
 impl Memory {
     pub fn new() -> Memory {
-        Memory { mem: [0; 0x10000], current_bank: 0, registers: [0; 0x100], mbc: MBC::None, interrupt_enable: 0, dma_transfer: false }
+        Memory {
+            model: Model::Dmg,
+            boot_rom: None,
+            vram: [0; VRAM_BANK_SIZE * 2],
+            wram: [0; WRAM_BANK_SIZE * 8],
+            vram_bank: 0,
+            wram_bank: 1,
+            oam: [0; 0xA0],
+            hram: [0; 0x7F],
+            cartridge: Cartridge::empty(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            timer: Timer::new(),
+            interrupt_flag: 0,
+            apu: Apu::new(),
+            ppu: Ppu::new(Renderer::Scanline),
+            dma_source: 0xFF,
+            mbc: MBC::None { ram: Vec::new() },
+            interrupt_enable: 0,
+            oam_dma: OamDma::new(),
+            hdma: Hdma::new(),
+            dma_stall: 0,
+            double_speed: false,
+            speed_switch_armed: false,
+            half_cycle: 0,
+            normal_cycles: 0,
+            rtc_synced: 0,
+            timers_synced: 0,
+            scheduler: Scheduler::new(),
+            rtc_clock: RtcClock::WallClock,
+            ram_dirty: false,
+        }
+    }
+
+    // Memory whose PPU draws with the given renderer, Memory::new uses the faster scanline renderer
+    pub fn with_renderer(renderer: Renderer) -> Memory {
+        let mut memory = Memory::new();
+        memory.ppu = Ppu::new(renderer);
+        memory
+    }
+
+    // Insert a cartridge, replacing any previously loaded one. The MBC is selected from the cartridge type byte
+    // and battery-backed RAM is restored from the .sav file next to the ROM
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) -> Result<(), CartridgeError> {
+        if let Err(err) = self.flush_save() {
+            eprintln!("Failed to write save file: {}", err);
+        }
+        let mbc = MBC::from_cartridge(&cartridge, self.rtc_clock)?;
+        let mut save = None;
+        if let Some(path) = cartridge.save_path() {
+            match fs::read(path) {
+                Ok(data) => save = Some(data),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        self.mbc = mbc;
+        self.cartridge = cartridge;
+        self.rtc_synced = self.normal_cycles;
+        if let Some(data) = save {
+            self.load_ram(&data);
+        }
+        self.reschedule(Event::RtcTick);
+        self.ram_dirty = false;
+        Ok(())
+    }
+
+    // Hardware model being emulated
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // Select the hardware model to emulate, the VRAM and WRAM bank registers, KEY1 and colour palettes only
+    // exist on the CGB
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.vram_bank = 0;
+        self.wram_bank = 1;
+        self.double_speed = false;
+        self.speed_switch_armed = false;
+        self.ppu.set_cgb(model.is_cgb());
+    }
+
+    // True while the CGB CPU runs at double speed
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    // Handle the CPU executing STOP. If a speed switch was armed through KEY1 the speed changes and true is
+    // returned, the CPU then stays stopped for SPEED_SWITCH_CYCLES. STOP also resets the divider
+    pub fn stop(&mut self) -> bool {
+        self.write_io(0xFF04, 0);
+        if !self.speed_switch_armed {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.speed_switch_armed = false;
+        self.half_cycle = 0;
+        // The PPU and the cartridge clock keep their speed, so their next events are a different number of CPU
+        // cycles away. The frame sequencer restarts with the divider it runs off
+        self.reschedule(Event::PpuMode);
+        self.reschedule(Event::ApuFrameSequencer);
+        self.sync_rtc();
+        self.reschedule(Event::RtcTick);
+        true
+    }
+
+    // Map a boot ROM over the start of the cartridge ROM, it stays mapped until 0xFF50 is written
+    pub fn load_boot_rom(&mut self, boot_rom: BootRom) {
+        self.boot_rom = Some(boot_rom);
+    }
+
+    // True while the boot ROM is mapped
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.is_some()
+    }
+
+    // Start without running a boot ROM: unmap it and put the I/O registers in the state the boot ROM
+    // leaves them in. Returns the CPU register values the boot ROM would have left behind
+    pub fn skip_boot(&mut self) -> PostBootRegisters {
+        self.boot_rom = None;
+        if self.model.is_cgb() && !self.cartridge.header().supports_cgb() {
+            self.start_compat_mode();
+        }
+        // IF goes last so interrupts raised while replaying the other registers, like STAT as the LCD switches
+        // on, are not left pending
+        let mut io = post_boot_io(self.model);
+        io.sort_by_key(|&(addr, _)| addr == 0xFF0F);
+        for (addr, val) in io {
+            match addr {
+                // DIV cannot be written, set it on the timer directly. The STAT mode follows from the PPU, which
+                // starts at the top of the frame as LCDC switches the LCD on
+                0xFF04 => {
+                    self.sync_timers();
+                    self.timer.set_div(val);
+                    self.reschedule(Event::TimerOverflow);
+                },
+                // Writing DMA would start a transfer, only its last value is left behind
+                0xFF46 => self.dma_source = val,
+                _ => self.write_byte(addr, val),
+            }
+        }
+        PostBootRegisters::new(self.model, self.cartridge.header())
+    }
+
+    // Put a CGB in DMG compatibility mode for a game without CGB support, as the boot ROM would. The boot ROM
+    // picks colours for the game from its title, the DMG greys stand in for them
+    fn start_compat_mode(&mut self) {
+        self.ppu.set_compat(true);
+        // Background palette 0, then object palettes 0 and 1
+        self.write_io(0xFF68, 0x80);
+        for byte in DMG_COLORS.iter().flat_map(|color| color.to_le_bytes()) {
+            self.write_io(0xFF69, byte);
+        }
+        self.write_io(0xFF6A, 0x80);
+        for byte in DMG_COLORS.iter().chain(DMG_COLORS.iter()).flat_map(|color| color.to_le_bytes()) {
+            self.write_io(0xFF6B, byte);
+        }
+    }
+
+    // Load a ROM image from a byte slice
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), CartridgeError> {
+        let cartridge = Cartridge::from_bytes(data)?;
+        self.load_cartridge(cartridge)
+    }
+
+    // Contents of the cartridge RAM in the raw .sav layout, followed by the clock footer on cartridges with a timer
+    pub fn save_ram(&self) -> Vec<u8> {
+        let mut data = self.mbc.ram().to_vec();
+        if let Some(rtc) = self.mbc.rtc() {
+            data.extend_from_slice(&rtc.footer());
+        }
+        data
+    }
+
+    // Restore the cartridge RAM from the raw .sav layout, including the clock footer if there is one
+    pub fn load_ram(&mut self, data: &[u8]) {
+        self.mbc.load_ram(data);
+        let ram_size = self.mbc.ram().len();
+        if let (Some(rtc), Some(footer)) = (self.mbc.rtc_mut(), data.get(ram_size..)) {
+            rtc.load_footer(footer);
+        }
+        self.reschedule(Event::RtcTick);
+    }
+
+    // Write battery-backed RAM to the .sav file next to the ROM if it changed since the last save.
+    // Cartridges with a clock are always written so the clock state is kept
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        if !self.ram_dirty && self.mbc.rtc().is_none() {
+            return Ok(());
+        }
+        if let Some(path) = self.cartridge.save_path() {
+            fs::write(path, self.save_ram())?;
+        }
+        self.ram_dirty = false;
+        Ok(())
+    }
+
+    // True if cartridge RAM was written since the last save
+    pub fn save_pending(&self) -> bool {
+        self.ram_dirty
+    }
+
+    // Select the time source for the cartridge real-time clock
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rtc_clock = clock;
+        self.sync_rtc();
+        if let Some(rtc) = self.mbc.rtc_mut() {
+            rtc.set_clock(clock);
+        }
+        self.reschedule(Event::RtcTick);
+    }
+
+    // Advance the hardware by the given number of CPU clock cycles, firing the scheduled events that fall
+    // inside them in order
+    pub fn step(&mut self, cycles: u32) {
+        let target = self.scheduler.now() + cycles as u64;
+        let mut last = self.scheduler.now();
+        while let Some(event) = self.scheduler.pop_due(target) {
+            let now = self.scheduler.now();
+            self.advance((now - last) as u32);
+            last = now;
+            self.handle_event(event);
+        }
+        self.scheduler.advance_to(target);
+        self.advance((target - last) as u32);
+    }
+
+    // CPU clock cycles until the next scheduled event, a halted CPU can skip straight to it
+    pub fn cycles_until_next_event(&self) -> Option<u64> {
+        self.scheduler.cycles_until_next()
+    }
+
+    // Bring the PPU up to date, it keeps running at the normal speed clock in double speed. The timer, serial
+    // port and cartridge clock only catch up when they are accessed or one of their events is due, see
+    // sync_timers and sync_rtc
+    fn advance(&mut self, cycles: u32) {
+        if cycles == 0 {
+            return;
+        }
+        let normal_cycles = self.normal_speed_cycles(cycles);
+        self.normal_cycles += normal_cycles as u64;
+        self.ppu.advance(normal_cycles, &self.vram);
+    }
+
+    // Pass the CPU clock cycles since the last sync on to the timer and serial port, which run on the CPU clock
+    // and speed up in double speed
+    fn sync_timers(&mut self) {
+        let mut cycles = self.scheduler.now() - self.timers_synced;
+        self.timers_synced = self.scheduler.now();
+        while cycles > 0 {
+            let step = cycles.min(u32::MAX as u64) as u32;
+            self.timer.tick(step);
+            self.serial.tick(step);
+            cycles -= step as u64;
+        }
+    }
+
+    // Pass the normal speed cycles since the last sync on to the cartridge clock
+    fn sync_rtc(&mut self) {
+        let cycles = u32::try_from(self.normal_cycles - self.rtc_synced).unwrap_or(u32::MAX);
+        self.rtc_synced = self.normal_cycles;
+        self.mbc.tick(cycles);
+    }
+
+    // Handle an event that came due. The PPU is already up to date with its time, the timer and serial port
+    // catch up as their events are rescheduled
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::TimerOverflow => self.trigger_interrupt(Interrupt::Timer),
+            Event::SerialTransfer => self.trigger_interrupt(Interrupt::Serial),
+            Event::OamDma => self.step_oam_dma(),
+            Event::PpuMode => self.step_ppu_mode(),
+            Event::ApuFrameSequencer => self.apu.step_frame_sequencer(),
+            Event::RtcTick => self.sync_rtc(),
+        }
+        self.reschedule(event);
+    }
+
+    // Schedule the next occurrence of an event from the current state of its component, or cancel it
+    fn reschedule(&mut self, event: Event) {
+        let cycles = match event {
+            Event::TimerOverflow => {
+                self.sync_timers();
+                self.timer.cycles_until_interrupt()
+            },
+            Event::SerialTransfer => {
+                self.sync_timers();
+                self.serial.cycles_until_done()
+            },
+            Event::OamDma => self.oam_dma.is_running().then_some(4),
+            Event::PpuMode => self.ppu.dots_until_next_mode().map(|dots| self.dots_to_cycles(dots)),
+            Event::ApuFrameSequencer => self.apu.enabled().then(|| self.dots_to_cycles(FRAME_SEQUENCER_PERIOD)),
+            Event::RtcTick => {
+                let cycles = self.mbc.rtc().and_then(|rtc| rtc.cycles_until_tick());
+                cycles.map(|cycles| self.dots_to_cycles(cycles))
+            },
+        };
+        match cycles {
+            Some(cycles) => self.scheduler.schedule(event, cycles as u64),
+            None => self.scheduler.cancel(event),
+        }
+    }
+
+    // Run one M-cycle of OAM DMA, copying a byte if a transfer is active
+    fn step_oam_dma(&mut self) {
+        if let Some((source, index)) = self.oam_dma.current() {
+            self.oam[index] = self.read_bus(source);
+        }
+        self.oam_dma.advance();
+    }
+
+    // Move the PPU on to its next mode, requesting the interrupts it raises and running H-Blank DMA
+    fn step_ppu_mode(&mut self) {
+        let requests = self.ppu.next_mode(&self.vram, &self.oam);
+        self.request_ppu_interrupts(requests);
+        if self.ppu.mode() == MODE_HBLANK {
+            self.hblank_dma();
+        }
+    }
+
+    // Request the interrupts the PPU asked for with REQUEST_VBLANK and REQUEST_STAT bits
+    fn request_ppu_interrupts(&mut self, requests: u8) {
+        if requests & REQUEST_VBLANK != 0 {
+            self.trigger_interrupt(Interrupt::VBlank);
+        }
+        if requests & REQUEST_STAT != 0 {
+            self.trigger_interrupt(Interrupt::LCDStat);
+        }
+    }
+
+    // Convert PPU dots to CPU clock cycles, twice as many in double speed less the half dot already counted
+    fn dots_to_cycles(&self, dots: u32) -> u32 {
+        if self.double_speed {
+            (dots * 2).saturating_sub(self.half_cycle)
+        } else {
+            dots
+        }
+    }
+
+    // Convert CPU clock cycles to cycles of the 4.194304 MHz clock, which are half as many in double speed
+    fn normal_speed_cycles(&mut self, cycles: u32) -> u32 {
+        if !self.double_speed {
+            return cycles;
+        }
+        let cycles = cycles + self.half_cycle;
+        self.half_cycle = cycles % 2;
+        cycles / 2
+    }
+
+    // Copy the next block of an H-Blank VRAM DMA transfer, called when the PPU enters H-Blank
+    pub fn hblank_dma(&mut self) {
+        if self.hdma.is_active() && self.hdma.is_hblank() {
+            self.hdma_block();
+        }
+    }
+
+    // CPU clock cycles the CPU has to stay halted for VRAM DMA since the last call. The caller steps
+    // the rest of the system through them
+    pub fn take_dma_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.dma_stall)
+    }
+
+    // Copy one 16-byte block of a VRAM DMA transfer into the selected video RAM bank
+    fn hdma_block(&mut self) {
+        if let Some((source, dest)) = self.hdma.next_block() {
+            for i in 0..HDMA_BLOCK_SIZE {
+                let byte = self.read_bus(source.wrapping_add(i));
+                self.vram[self.vram_bank as usize * VRAM_BANK_SIZE + (dest + i) as usize] = byte;
+            }
+            // The copy takes the same time in double speed, which is twice as many CPU cycles
+            self.dma_stall += if self.double_speed { HDMA_BLOCK_CYCLES * 2 } else { HDMA_BLOCK_CYCLES };
+        }
+    }
+
+    // Press a joypad button, requesting the joypad interrupt when a selected line goes low
+    pub fn press_button(&mut self, button: Button) {
+        if self.joypad.press(button) {
+            self.trigger_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    // Release a joypad button
+    pub fn release_button(&mut self, button: Button) {
+        self.joypad.release(button);
     }
 
     // Read a byte from memory at the given address, taking memory banking, I/O registers, and MBC into account
-    pub fn read_byte(&self, addr: u16) -> u8 {
-        if self.dma_transfer {
-            return 0xFF;
-        }if addr < 0x4000 {
-            // Non-banked memory, always accessible
-            self.mem[addr as usize]
+    pub fn read_byte(&mut self, addr: u16) -> u8 {
+        if matches!(addr, 0xFF01..=0xFF02 | 0xFF04..=0xFF07) {
+            self.sync_timers();
+        }
+        if let Some((source, _)) = self.oam_dma.current() {
+            if (0xFE00..0xFF00).contains(&addr) {
+                // OAM is being written by the transfer
+                return 0xFF;
+            }
+            if Bus::of(addr) == Bus::of(source) {
+                // Bus conflict, the CPU sees the byte the transfer is reading
+                return self.read_bus(source);
+            }
+        }
+        self.read_bus(addr)
+    }
+
+    // Read a byte as seen on the memory buses, without OAM DMA conflicts
+    fn read_bus(&self, addr: u16) -> u8 {
+        if let Some(byte) = self.boot_rom.as_ref().and_then(|boot_rom| boot_rom.read(addr)) {
+            // Boot ROM overlay
+            byte
+        } else if addr < 0x4000 {
+            // Fixed ROM area, bank 0 unless the MBC remaps it
+            self.cartridge.read_rom_bank(self.mbc.get_zero_bank(), addr)
         } else if addr < 0x8000 {
-            // Banked memory, bank number is determined by current_bank and MBC
-            let bank_offset = (self.mbc.get_bank(self.current_bank) as u16) * 0x4000;
-            self.mem[(addr + bank_offset) as usize]
+            // Banked ROM, the bank number is determined by the MBC
+            let bank = self.mbc.get_bank();
+            self.cartridge.read_rom_bank(bank, addr)
+        } else if addr < 0xA000 {
+            // Video RAM
+            self.vram[self.vram_offset(addr)]
+        } else if addr < 0xC000 {
+            // External cartridge RAM
+            self.mbc.read_ram(addr)
+        } else if addr < 0xE000 {
+            // Work RAM
+            self.wram[self.wram_offset(addr)]
+        } else if addr < 0xFE00 {
+            // Echo RAM, mirrors 0xC000-0xDDFF
+            self.wram[self.wram_offset(addr - 0x2000)]
+        } else if addr < 0xFEA0 {
+            // OAM, the PPU owns it while scanning OAM and drawing
+            if self.oam_blocked() {
+                0xFF
+            } else {
+                self.oam[addr as usize - 0xFE00]
+            }
         } else if addr < 0xFF00 {
-            // Remaining memory is not banked
-            self.mem[addr as usize]
-        } else if addr < 0xFFFF {
+            // Unusable area
+            self.read_unusable(addr)
+        } else if addr < 0xFF80 {
             // I/O registers
-            self.registers[addr as usize - 0xFF00]
+            self.read_io(addr)
+        } else if addr < 0xFFFF {
+            // High RAM
+            self.hram[addr as usize - 0xFF80]
         } else {
             // Interrupt enable register
             self.interrupt_enable
@@ -43,243 +539,321 @@ impl Memory {
 
     // Write a byte to memory at the given address, taking memory banking, I/O registers, and MBC into account
     pub fn write_byte(&mut self, addr: u16, val: u8) {
-        if self.dma_transfer {
-            return;
+        if let Some((source, _)) = self.oam_dma.current() {
+            // Writes to OAM and to the bus the transfer is reading from are lost
+            if (0xFE00..0xFF00).contains(&addr) || Bus::of(addr) == Bus::of(source) {
+                return;
+            }
         }
-    if addr < 0x4000 {
-            // Non-banked memory, always accessible
-            self.mem[addr as usize] = val;
-        } else if addr < 0x8000 {
-            // Banked memory, bank number is determined by MBC
-            self.mbc.set_bank(addr, val, &mut self.mem);
+        if addr < 0x8000 {
+            // ROM is read-only, writes go to the MBC registers. They can latch or halt the cartridge clock,
+            // so it is brought up to date first
+            self.sync_rtc();
+            self.mbc.set_bank(addr, val);
+            self.reschedule(Event::RtcTick);
+        } else if addr < 0xA000 {
+            // Video RAM
+            let offset = self.vram_offset(addr);
+            self.vram[offset] = val;
+        } else if addr < 0xC000 {
+            // External cartridge RAM, or the cartridge clock registers
+            self.sync_rtc();
+            if self.mbc.write_ram(addr, val) {
+                self.ram_dirty = true;
+            }
+            self.reschedule(Event::RtcTick);
+        } else if addr < 0xE000 {
+            // Work RAM
+            let offset = self.wram_offset(addr);
+            self.wram[offset] = val;
+        } else if addr < 0xFE00 {
+            // Echo RAM, mirrors 0xC000-0xDDFF
+            let offset = self.wram_offset(addr - 0x2000);
+            self.wram[offset] = val;
+        } else if addr < 0xFEA0 {
+            // OAM, writes are ignored while the PPU owns it
+            if !self.oam_blocked() {
+                self.oam[addr as usize - 0xFE00] = val;
+            }
         } else if addr < 0xFF00 {
-            // Remaining memory is not banked
-            self.mem[addr as usize] = val;
-        } else if addr < 0xFFFF {
+            // Unusable area, writes are ignored
+        } else if addr < 0xFF80 {
             // I/O registers
-            self.registers[addr as usize - 0xFF00] = val;
+            self.write_io(addr, val);
+        } else if addr < 0xFFFF {
+            // High RAM
+            self.hram[addr as usize - 0xFF80] = val;
         } else {
             // Interrupt enable register
             self.interrupt_enable = val;
         }
     }
 
-// Set the current bank number for banked memory
-pub fn set_bank(&mut self, bank: u8) {
-    self.current_bank = bank;
-}
-
-// Read a word (2 bytes) from memory at the given address, taking memory banking, I/O registers, and MBC into account
-pub fn read_word(&self, addr: u16) -> u16 {
-    let low = self.read_byte(addr) as u16;
-    let high = self.read_byte(addr + 1) as u16;
-    (high << 8) | low
-}
-
-// Write a word (2 bytes) to memory at the given address, taking memory banking, I/O registers, and MBC into account
-pub fn write_word(&mut self, addr: u16, val: u16) {
-    self.write_byte(addr, (val & 0xFF) as u8);
-    self.write_byte(addr + 1, (val >> 8) as u8);
-}
+    // Read an I/O register from the component that owns it, unmapped registers read as 0xFF
+    fn read_io(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF00 => self.joypad.read(),
+            0xFF01..=0xFF02 => self.serial.read(addr),
+            0xFF04..=0xFF07 => self.timer.read(addr),
+            // Only the low 5 bits of IF exist, the rest read as 1
+            0xFF0F => 0b1110_0000 | self.interrupt_flag,
+            0xFF10..=0xFF3F => self.apu.read(addr),
+            0xFF46 => self.dma_source,
+            0xFF40..=0xFF4B => self.ppu.read(addr),
+            // Only bit 0 of VBK and bits 0-2 of SVBK exist, the rest read as 1
+            0xFF4D if self.model.is_cgb() => 0b0111_1110 | (self.double_speed as u8) << 7 | self.speed_switch_armed as u8,
+            0xFF4F if self.model.is_cgb() => 0b1111_1110 | self.vram_bank,
+            0xFF51..=0xFF55 if self.model.is_cgb() => self.hdma.read(addr),
+            0xFF68..=0xFF6C if self.model.is_cgb() => self.ppu.read(addr),
+            0xFF70 if self.model.is_cgb() => 0b1111_1000 | self.wram_bank,
+            _ => 0xFF,
+        }
+    }
 
-// Check if an interrupt is enabled and should be triggered
-pub fn check_interrupt(&self, interrupt: Interrupt) -> bool {
-    let interrupt_flag = match interrupt {
-        Interrupt::VBlank => 0x01,
-        Interrupt::LCDStat => 0x02,
-        Interrupt::Timer => 0x04,
-        Interrupt::Serial => 0x08,
-        Interrupt::Joypad => 0x10,
-    };
-    (self.interrupt_enable & interrupt_flag) != 0
-}
+    // Write an I/O register to the component that owns it, triggering any side effects
+    fn write_io(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF00 => self.joypad.write(val),
+            0xFF01..=0xFF02 => {
+                self.sync_timers();
+                self.serial.write(addr, val);
+                self.reschedule(Event::SerialTransfer);
+            },
+            0xFF04..=0xFF07 => {
+                self.sync_timers();
+                self.timer.write(addr, val);
+                self.reschedule(Event::TimerOverflow);
+                if addr == 0xFF04 {
+                    // The frame sequencer runs off the divider
+                    self.reschedule(Event::ApuFrameSequencer);
+                }
+            },
+            0xFF0F => self.interrupt_flag = val & 0b0001_1111,
+            0xFF10..=0xFF3F => {
+                self.apu.write(addr, val);
+                if addr == 0xFF26 && self.apu.enabled() != self.scheduler.is_scheduled(Event::ApuFrameSequencer) {
+                    // Powering the APU on starts the frame sequencer, powering it off stops it
+                    self.reschedule(Event::ApuFrameSequencer);
+                }
+            },
+            0xFF46 => {
+                self.dma_source = val;
+                self.dma_transfer(val);
+            },
+            0xFF40..=0xFF4B => {
+                let requests = self.ppu.write(addr, val);
+                self.request_ppu_interrupts(requests);
+                if addr == 0xFF40 {
+                    self.reschedule(Event::PpuMode);
+                }
+            },
+            // KEY0 can only be written by the boot ROM, bit 2 selects DMG compatibility mode
+            0xFF4C if self.model.is_cgb() && self.boot_rom.is_some() => self.ppu.set_compat(val & 0b0000_0100 != 0),
+            0xFF4D if self.model.is_cgb() => self.speed_switch_armed = val & 0b0000_0001 != 0,
+            0xFF4F if self.model.is_cgb() => self.vram_bank = val & 0b0000_0001,
+            0xFF51..=0xFF55 if self.model.is_cgb() => {
+                self.hdma.write(addr, val);
+                if addr == 0xFF55 {
+                    self.start_hdma();
+                }
+            },
+            0xFF68..=0xFF6C if self.model.is_cgb() => {
+                self.ppu.write(addr, val);
+            },
+            0xFF70 if self.model.is_cgb() => self.wram_bank = val & 0b0000_0111,
+            // Any non-zero value unmaps the boot ROM, it cannot be mapped again
+            0xFF50 if val != 0 => self.boot_rom = None,
+            _ => (),
+        }
+    }
 
-// Trigger an interrupt
-pub fn trigger_interrupt(&mut self, interrupt: Interrupt) {
-    if !self.check_interrupt(interrupt) {
-        return;
-    }
-    // Set the interrupt flag in memory
-    let flag_addr = match interrupt {
-        Interrupt::VBlank => 0xFF0F,
-        Interrupt::LCDStat => 0xFF0F,
-        Interrupt::Timer => 0xFF0F,
-        Interrupt::Serial => 0xFF0F,
-        Interrupt::Joypad => 0xFF0F,
-    };
-    let flag = self.read_byte(flag_addr);
-    self.write_byte(flag_addr, flag | 0x01);
-}
+    // Run a VRAM DMA transfer just started by writing HDMA5. A general purpose transfer copies every block
+    // straight away, an H-Blank transfer copies its first block now if the PPU is already in H-Blank or the LCD is off
+    fn start_hdma(&mut self) {
+        if !self.hdma.is_active() {
+            return;
+        }
+        if !self.hdma.is_hblank() {
+            while self.hdma.is_active() {
+                self.hdma_block();
+            }
+        } else if !self.ppu.lcd_enabled() || self.ppu.mode() == MODE_HBLANK {
+            self.hdma_block();
+        }
+    }
 
-// Perform a DMA transfer
-pub fn dma_transfer(&mut self, source: u8) {
-    if self.dma_transfer {
-        // DMA transfer already in progress
-        return;
+    // Offset into vram of a 0x8000-0x9FFF address in the selected bank
+    fn vram_offset(&self, addr: u16) -> usize {
+        self.vram_bank as usize * VRAM_BANK_SIZE + (addr as usize - 0x8000)
     }
-    self.dma_transfer = true;
-    let source_addr = (source as u16) << 8;
-    for i in 0xFE00..0xFEA0 {
-        self.write_byte(i, self.read_byte(source_addr + (i - 0xFE00)));
+
+    // Offset into wram of a 0xC000-0xDFFF address, 0xD000-0xDFFF maps the bank selected by SVBK
+    // where bank 0 selects bank 1
+    fn wram_offset(&self, addr: u16) -> usize {
+        if addr < 0xD000 {
+            addr as usize - 0xC000
+        } else {
+            self.wram_bank.max(1) as usize * WRAM_BANK_SIZE + (addr as usize - 0xD000)
+        }
     }
-    self.dma_transfer = false;
-}
-}
 
-// MBC (Memory Bank Controller) enum
-pub enum MBC {
-    None,
-    MBC1 {
-        ram_enable: bool,
-        ram_bank: u8,
-        rom_bank: u8,
-        rom_mode: bool,
-    },
-    MBC2 {
-        ram_enable: bool,
-        rom_bank: u8,
-    },
-    // Add other MBC variants here
-}
+    // True while the PPU is scanning OAM (mode 2) or drawing (mode 3) and the CPU cannot access OAM
+    fn oam_blocked(&self) -> bool {
+        matches!(self.ppu.mode(), MODE_OAM_SCAN | MODE_DRAWING)
+    }
 
-impl MBC {
-    fn get_bank(&self, current_bank: u8) -> u8 {
-        match self {
-            MBC::None => current_bank,
-            MBC::MBC1 { ram_enable, ram_bank, rom_bank, rom_mode } => {
-                if *rom_mode {
-                    *rom_bank & 0b0001_1111
-                } else {
-                    (*ram_bank << 5) | (*rom_bank & 0b0001_1111)
-                }
+    // Read from the unusable area at 0xFEA0-0xFEFF, the result depends on the model
+    fn read_unusable(&self, addr: u16) -> u8 {
+        if self.oam_blocked() {
+            return 0xFF;
+        }
+        match self.model {
+            // The CGB repeats the upper nibble of the low address byte
+            Model::Cgb => {
+                let nibble = addr as u8 & 0xF0;
+                nibble | (nibble >> 4)
             },
-            MBC::MBC2 { ram_enable, rom_bank } => *rom_bank & 0b0000_1111,
-            // Add other MBC variants here
+            Model::Dmg | Model::Mgb | Model::Sgb => 0x00,
         }
     }
 
-    fn set_bank(&mut self, addr: u16, val: u8, mem: &mut [u8]) {
-        match self {
-            MBC::None => (),
-            MBC::MBC1 { ram_enable, ram_bank, rom_bank, rom_mode } => {
-                if addr < 0x2000 {
-                    // Enable/disable RAM
-                    *ram_enable = val & 0b0000_1111 == 0b0000_1010;
-                } else if addr < 0x4000 {
-                    // Set low 5 bits of ROM bank number
-                    *rom_bank = (*rom_bank & 0b1110_0000) | (val & 0b0001_1111);
-                } else if addr < 0x6000 {
-                    if *rom_mode {
-                        // Set high bit of ROM bank number
-                        *rom_bank = (*rom_bank & 0b0001_1111) | ((val & 0b0000_0001) << 5);
-                    } else {
-                        // Set RAM bank number
-                        *ram_bank = val & 0b0000_0011;
-                    }
-                } else if addr < 0x8000 {
-                    // Set ROM/RAM mode
-                    *rom_mode = val & 0b0000_0001 == 0;
-                }
-            },
-            MBC::MBC2 { ram_enable, rom_bank } => {
-                if addr < 0x2000 {
-                    // Enable/disable RAM
-                    *ram_enable = val & 0b0000_0001 == 0b0000_0001;
-                } else if addr < 0x4000 {
-                    // Set low 4 bits of ROM bank number
-                    *rom_bank = (*rom_bank & 0b1111_0000) | (val & 0b0000_1111);
-                }
-            },
-            // Add other MBC variants here
+    // Inserted cartridge
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
+    }
+
+    // MBC of the inserted cartridge
+    pub fn mbc(&self) -> &MBC {
+        &self.mbc
+    }
+
+    // True while the cartridge rumble motor is switched on, polled by the frontend to drive a gamepad
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    // Bytes sent over the serial port so far
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+
+    // PPU state
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    // Mutable access to the PPU state
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    // Read a word (2 bytes) from memory at the given address, taking memory banking, I/O registers, and MBC into account
+    pub fn read_word(&mut self, addr: u16) -> u16 {
+        let low = self.read_byte(addr) as u16;
+        let high = self.read_byte(addr.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    // Write a word (2 bytes) to memory at the given address, taking memory banking, I/O registers, and MBC into account
+    pub fn write_word(&mut self, addr: u16, val: u16) {
+        self.write_byte(addr, (val & 0xFF) as u8);
+        self.write_byte(addr.wrapping_add(1), (val >> 8) as u8);
+    }
+
+    // Check if an interrupt is enabled in IE
+    pub fn check_interrupt(&self, interrupt: Interrupt) -> bool {
+        (self.interrupt_enable & interrupt.bit()) != 0
+    }
+
+    // Trigger an interrupt by setting its bit in IF, IF latches requests whether or not IE enables them
+    pub fn trigger_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.bit();
+    }
+
+    // Highest priority interrupt that is both requested and enabled, the CPU dispatches this one next
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.interrupt_enable & self.interrupt_flag & 0b0001_1111;
+        Interrupt::ALL.into_iter().find(|interrupt| pending & interrupt.bit() != 0)
+    }
+
+    // Acknowledge an interrupt by clearing its IF bit, done by the CPU when it dispatches the interrupt
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.bit();
+    }
+
+    // Start an OAM DMA transfer from the given source page, it runs for 160 M-cycles as the system is stepped
+    pub fn dma_transfer(&mut self, source: u8) {
+        self.oam_dma.start(source);
+        if !self.scheduler.is_scheduled(Event::OamDma) {
+            self.reschedule(Event::OamDma);
         }
     }
 }
-impl MBC {
-    // Get the current bank number for banked memory
-    pub fn get_bank(&self, bank: u8) -> u8 {
-        match self {
-            MBC::None => bank,
-            MBC::MBC1 => {
-                // MBC1 can use either the lower 5 bits of the bank number or the upper 2 bits, depending on the ROM/RAM mode
-                if self.rom_mode {
-                    bank & 0x1F
-                } else {
-                    (bank & 0x03) | (self.ram_bank as u8)
-                }
-            }
-            MBC::MBC2 => bank & 0x0F,
-            MBC::MBC3 => bank,
-            MBC::MBC5 => bank,
+
+// Memory buses an OAM DMA transfer can occupy, the CPU keeps access to the internal one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Bus {
+    // Cartridge ROM and RAM, and work RAM
+    External,
+    // Video RAM
+    Video,
+    // OAM, I/O registers and HRAM inside the CPU
+    Internal,
+}
+
+impl Bus {
+    // Bus an address is reached through
+    fn of(addr: u16) -> Bus {
+        match addr {
+            0x8000..=0x9FFF => Bus::Video,
+            0xFE00..=0xFFFF => Bus::Internal,
+            _ => Bus::External,
         }
     }
-    // Set the current bank number for banked memory
-    pub fn set_bank(&mut self, addr: u16, val: u8, mem: &mut [u8]) {
-        match self {
-            MBC::None => {}
-            MBC::MBC1 => {
-                if addr < 0x2000 {
-                    // Enable/disable RAM
-                    self.ram_enable = val & 0x0F == 0x0A;
-                } else if addr < 0x4000 {
-                    // Set ROM bank number
-                    self.rom_bank = (self.rom_bank & 0x60) | (val & 0x1F);
-                } else if addr < 0x6000 {
-                    // Set RAM bank number or ROM/RAM mode
-                    if self.rom_mode {
-                        self.rom_bank = (self.rom_bank & 0x1F) | ((val & 0x03) << 5);
-                    } else {
-                        self.ram_bank = val & 0x03;
-                    }
-                } else if addr < 0x8000 {
-                    // Set ROM/RAM mode
-                    self.rom_mode = val & 0x01 == 0x00;
-                }
-            }
-            MBC::MBC2 => {
-                if addr < 0x2000 {
-                    // Enable/disable RAM
-                    self.ram_enable = val & 0x0F == 0x0A;
-                } else if addr < 0x4000 {
-                    // Set ROM bank number (lower 4 bits only)
-                    self.rom_bank = val & 0x0F;
-                }
-            }
-            MBC::MBC3 => {
-                if addr < 0x2000 {
-                    // Enable/disable RAM
-                    self.ram_enable = val & 0x0F == 0x0A;
-                } else if addr < 0x4000 {
-                    // Set ROM bank number
-                    self.rom_bank = val & 0x7F;
-                } else if addr < 0x6000 {
-                    // Set RAM bank number
-                    self.ram_bank = val & 0x03;
-                }
-            }
-            MBC::MBC5 => {
-                if addr < 0x2000 {
-                    // Enable/disable RAM
-                    self.ram_enable = val & 0x0F == 0x0A;
-                } else if addr < 0x3000 {
-                    // Set lower 8 bits of ROM bank number
-                    self.rom_bank = (self.rom_bank & 0x0100) | val;
-                } else if addr < 0x4000 {
-                    // Set upper bit of ROM bank number
-                    self.rom_bank = (self.rom_bank & 0x00FF) | ((val & 0x01) << 8);
-                } else if addr < 0x6000 {
-                    // Set RAM bank number
-                    self.ram_bank = val & 0x0F;
-                }
-            }
+}
+
+impl Drop for Memory {
+    // Make sure battery-backed RAM survives the emulator exiting
+    fn drop(&mut self) {
+        if let Err(err) = self.flush_save() {
+            eprintln!("Failed to write save file: {}", err);
         }
     }
 }
 
 // Interrupt enum
-enum Interrupt {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
     VBlank,
     LCDStat,
     Timer,
     Serial,
     Joypad,
+}
+
+impl Interrupt {
+    // All interrupts from highest to lowest priority
+    pub const ALL: [Interrupt; 5] = [Interrupt::VBlank, Interrupt::LCDStat, Interrupt::Timer, Interrupt::Serial, Interrupt::Joypad];
+
+    // Bit of the interrupt in the IE and IF registers
+    pub fn bit(&self) -> u8 {
+        match self {
+            Interrupt::VBlank => 0x01,
+            Interrupt::LCDStat => 0x02,
+            Interrupt::Timer => 0x04,
+            Interrupt::Serial => 0x08,
+            Interrupt::Joypad => 0x10,
+        }
+    }
+
+    // Address the CPU jumps to when dispatching the interrupt
+    pub fn vector(&self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x0040,
+            Interrupt::LCDStat => 0x0048,
+            Interrupt::Timer => 0x0050,
+            Interrupt::Serial => 0x0058,
+            Interrupt::Joypad => 0x0060,
+        }
+    }
 }
\ No newline at end of file
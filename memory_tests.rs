@@ -1,4 +1,16 @@
-use crate::memory::Memory;
+use std::env;
+use std::fs;
+use std::process;
+use std::thread;
+
+use crate::boot::Model;
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::cartridge_tests::{build_rom, fix_checksums};
+use crate::cpu::Cpu;
+use crate::joypad::Button;
+use crate::mbc::MBC;
+use crate::memory::{Interrupt, Memory};
+use crate::rtc::{RtcClock, CYCLES_PER_SECOND, RTC_FOOTER_SIZE};
 
 #[cfg(test)]
 pub mod tests {
@@ -10,8 +22,10 @@ pub mod tests {
         let test_addr = 0x2000; // Any address in the non-banked memory range (0x0000 - 0x3FFF)
         let test_val = 0xAA;
 
-        // Write a value to the test address
-        memory.write_byte(test_addr, test_val);
+        // Load a ROM with a known value at the test address
+        let mut rom = build_rom(0x00, 0x00, 0x00);
+        rom[test_addr as usize] = test_val;
+        memory.load_rom(&rom).unwrap();
 
         // Read from the test address and verify the correct value is returned
         assert_eq!(memory.read_byte(test_addr), test_val);
@@ -21,24 +35,31 @@ pub mod tests {
     pub fn test_banked_memory() {
         let mut memory = Memory::new();
         let test_addr = 0x4000; // Any address in the banked memory range (0x4000 - 0x7FFF)
-        let test_val = 0xAA;
 
-        // Write a value to the test address
-        memory.write_byte(test_addr, test_val);
+        // Load an MBC1 ROM larger than the 32KB address window
+        memory.load_rom(&build_rom(0x01, 0x01, 0x00)).unwrap();
 
-        // Change the current bank number and read from the same address
-        memory.set_bank(1);
-        assert_ne!(memory.read_byte(test_addr), test_val);
+        // Bank 1 is mapped by default
+        assert_eq!(memory.read_byte(test_addr), 1);
 
-        // Change the current bank number back and verify the correct value is returned
-        memory.set_bank(0);
-        assert_eq!(memory.read_byte(test_addr), test_val);
+        // Select another bank through the MBC and read from the same address
+        memory.write_byte(0x2000, 3);
+        assert_eq!(memory.read_byte(test_addr), 3);
+
+        // Select bank 1 again and verify the correct value is returned
+        memory.write_byte(0x2000, 1);
+        assert_eq!(memory.read_byte(test_addr), 1);
+
+        // Without an MBC bank 1 stays mapped, ROM writes go nowhere
+        memory.load_rom(&build_rom(0x00, 0x00, 0x00)).unwrap();
+        memory.write_byte(0x2000, 0);
+        assert_eq!(memory.read_byte(test_addr), 1);
     }
 
     #[test]
     pub fn test_io_registers() {
         let mut memory = Memory::new();
-        let test_addr = 0xFF00; // Any address in the I/O registers range (0xFF00 - 0xFF7F)
+        let test_addr = 0xFF42; // Any fully writable register in the I/O registers range (0xFF00 - 0xFF7F)
         let test_val = 0xAA;
 
         // Write a value to the test address
@@ -51,7 +72,7 @@ pub mod tests {
     #[test]
     pub fn test_read_write_word() {
         let mut memory = Memory::new();
-        let test_addr = 0x2000; // Any address in the non-banked memory range (0x0000 - 0x3FFF)
+        let test_addr = 0xC000; // Any address in work RAM (0xC000 - 0xDFFF)
         let test_val = 0xAABB;
 
         // Write a value to the test address using write_word
@@ -60,4 +81,437 @@ pub mod tests {
         // Read from the test address using read_word and verify the correct value is returned
         assert_eq!(memory.read_word(test_addr), test_val);
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_rom_is_read_only() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x00, 0x00, 0x00)).unwrap();
+
+        // Writes to the ROM area must not change the ROM contents
+        memory.write_byte(0x0200, 0xAA);
+        assert_eq!(memory.read_byte(0x0200), 0);
+    }
+
+    #[test]
+    pub fn test_load_rom_too_large() {
+        let mut memory = Memory::new();
+
+        // Images larger than 8MB cannot be addressed by any MBC
+        assert!(memory.load_rom(&vec![0; 0x80_0000 + 1]).is_err());
+    }
+
+    #[test]
+    pub fn test_mbc_selected_from_header() {
+        let mut memory = Memory::new();
+
+        memory.load_rom(&build_rom(0x01, 0x02, 0x00)).unwrap();
+        assert!(matches!(memory.mbc(), MBC::MBC1 { .. }));
+
+        memory.load_rom(&build_rom(0x06, 0x01, 0x00)).unwrap();
+        assert!(matches!(memory.mbc(), MBC::MBC2 { .. }));
+
+        // Cartridge types we do not emulate are rejected with a typed error
+        assert!(matches!(memory.load_rom(&build_rom(0xFE, 0x00, 0x00)), Err(CartridgeError::UnsupportedCartridgeType(0xFE))));
+    }
+
+    #[test]
+    pub fn test_save_ram() {
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x03, 0x02, 0x02)).unwrap();
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0xA000, 0x12);
+        memory.write_byte(0xBFFF, 0x34);
+
+        // The save is the raw RAM contents
+        let save = memory.save_ram();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!((save[0], save[0x1FFF]), (0x12, 0x34));
+
+        let mut memory = Memory::new();
+        memory.load_rom(&build_rom(0x03, 0x02, 0x02)).unwrap();
+        memory.load_ram(&save);
+        memory.write_byte(0x0000, 0x0A);
+        assert_eq!(memory.read_byte(0xA000), 0x12);
+        assert_eq!(memory.read_byte(0xBFFF), 0x34);
+    }
+
+    #[test]
+    pub fn test_save_file() {
+        let dir = env::temp_dir().join(format!("gptboy-save-{}-{:?}", process::id(), thread::current().id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.gb");
+        let sav_path = dir.join("game.sav");
+        fs::write(&rom_path, build_rom(0x1B, 0x02, 0x02)).unwrap();
+
+        // Written when the memory is dropped
+        {
+            let mut memory = Memory::new();
+            memory.load_cartridge(Cartridge::from_file(&rom_path).unwrap()).unwrap();
+            memory.write_byte(0x0000, 0x0A);
+            memory.write_byte(0xA123, 0x56);
+        }
+        let save = fs::read(&sav_path).unwrap();
+        assert_eq!(save.len(), 0x2000);
+        assert_eq!(save[0x0123], 0x56);
+
+        // Restored when the cartridge is loaded again
+        let mut memory = Memory::new();
+        memory.load_cartridge(Cartridge::from_file(&rom_path).unwrap()).unwrap();
+        memory.write_byte(0x0000, 0x0A);
+        assert_eq!(memory.read_byte(0xA123), 0x56);
+
+        // Writes while the RAM is disabled change nothing, so there is nothing to save
+        fs::remove_file(&sav_path).unwrap();
+        memory.write_byte(0x0000, 0x00);
+        memory.write_byte(0xA123, 0x78);
+        memory.flush_save().unwrap();
+        assert!(!sav_path.exists());
+
+        // Cartridges without a battery never write a save file
+        fs::write(&rom_path, build_rom(0x1A, 0x02, 0x02)).unwrap();
+        memory.load_cartridge(Cartridge::from_file(&rom_path).unwrap()).unwrap();
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0xA000, 0x56);
+        memory.flush_save().unwrap();
+        assert!(!sav_path.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    pub fn test_main_loop_saves() {
+        let dir = env::temp_dir().join(format!("gptboy-loop-{}-{:?}", process::id(), thread::current().id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.gb");
+        let sav_path = dir.join("game.sav");
+
+        // The game enables RAM, writes 0x56 to 0xA000 and loops
+        let mut rom = build_rom(0x03, 0x02, 0x02);
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x0150..0x015C].copy_from_slice(&[0x3E, 0x0A, 0xEA, 0x00, 0x00, 0x3E, 0x56, 0xEA, 0x00, 0xA0, 0x18, 0xFE]);
+        fix_checksums(&mut rom);
+        fs::write(&rom_path, rom).unwrap();
+
+        // The save is written at the end of the frame, without waiting for Memory to be dropped
+        let mut memory = Memory::new();
+        memory.load_cartridge(Cartridge::from_file(&rom_path).unwrap()).unwrap();
+        let mut cpu = Cpu::from_registers(memory.skip_boot());
+        crate::run(&mut cpu, &mut memory, Some(1));
+        assert!(!memory.save_pending());
+        assert_eq!(fs::read(&sav_path).unwrap()[0], 0x56);
+
+        drop(memory);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    pub fn test_save_ram_with_clock() {
+        let mut memory = Memory::new();
+        memory.set_rtc_clock(RtcClock::Cycles);
+        memory.load_rom(&build_rom(0x10, 0x02, 0x02)).unwrap();
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0xA000, 0x12);
+        memory.write_byte(0x4000, 0x0A);
+        memory.write_byte(0xA000, 17);
+
+        // The clock footer follows the RAM contents
+        let save = memory.save_ram();
+        assert_eq!(save.len(), 0x2000 + RTC_FOOTER_SIZE);
+        assert_eq!(save[0x2000 + 8], 17);
+
+        let mut memory = Memory::new();
+        memory.set_rtc_clock(RtcClock::Cycles);
+        memory.load_rom(&build_rom(0x10, 0x02, 0x02)).unwrap();
+        memory.load_ram(&save);
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0x6000, 0x00);
+        memory.write_byte(0x6000, 0x01);
+        memory.write_byte(0x4000, 0x0A);
+        assert_eq!(memory.read_byte(0xA000), 17);
+        memory.write_byte(0x4000, 0x00);
+        assert_eq!(memory.read_byte(0xA000), 0x12);
+    }
+
+    #[test]
+    pub fn test_echo_ram() {
+        let mut memory = Memory::new();
+
+        // 0xE000-0xFDFF mirrors work RAM in both directions
+        memory.write_byte(0xC123, 0x11);
+        assert_eq!(memory.read_byte(0xE123), 0x11);
+        memory.write_byte(0xFDFF, 0x22);
+        assert_eq!(memory.read_byte(0xDDFF), 0x22);
+    }
+
+    #[test]
+    pub fn test_oam_access() {
+        let mut memory = Memory::new();
+        memory.write_byte(0xFE00, 0x33);
+        assert_eq!(memory.read_byte(0xFE00), 0x33);
+
+        // While scanning OAM or drawing, the CPU reads 0xFF and its writes are dropped
+        memory.write_byte(0xFF40, 0x80);
+        for mode in [2, 3] {
+            memory.ppu_mut().set_mode(mode);
+            assert_eq!(memory.read_byte(0xFE00), 0xFF);
+            memory.write_byte(0xFE00, 0x44);
+            assert_eq!(memory.read_byte(0xFEA0), 0xFF);
+        }
+
+        // OAM is accessible again in H-Blank and V-Blank
+        memory.ppu_mut().set_mode(0);
+        assert_eq!(memory.read_byte(0xFE00), 0x33);
+        memory.ppu_mut().set_mode(1);
+        assert_eq!(memory.read_byte(0xFE00), 0x33);
+    }
+
+    #[test]
+    pub fn test_unusable_area() {
+        let mut memory = Memory::new();
+
+        // Reads 0x00 on DMG and writes are ignored
+        memory.write_byte(0xFEA0, 0x55);
+        assert_eq!(memory.read_byte(0xFEA0), 0x00);
+        assert_eq!(memory.read_byte(0xFEFF), 0x00);
+
+        // The CGB repeats the upper nibble of the low address byte
+        memory.set_model(Model::Cgb);
+        assert_eq!(memory.read_byte(0xFEA0), 0xAA);
+        assert_eq!(memory.read_byte(0xFEB7), 0xBB);
+        assert_eq!(memory.read_byte(0xFEFF), 0xFF);
+    }
+
+    #[test]
+    pub fn test_hram() {
+        let mut memory = Memory::new();
+
+        // High RAM is separate from the I/O registers and the interrupt enable register
+        memory.write_byte(0xFF80, 0x66);
+        memory.write_byte(0xFFFE, 0x77);
+        memory.write_byte(0xFFFF, 0x1F);
+        assert_eq!(memory.read_byte(0xFF80), 0x66);
+        assert_eq!(memory.read_byte(0xFFFE), 0x77);
+        assert_eq!(memory.read_byte(0xFFFF), 0x1F);
+    }
+
+    #[test]
+    pub fn test_io_read_masks() {
+        let mut memory = Memory::new();
+
+        // Unused and write-only bits read as 1
+        memory.write_byte(0xFF00, 0x00);
+        assert_eq!(memory.read_byte(0xFF00), 0xCF);
+        memory.write_byte(0xFF02, 0x00);
+        assert_eq!(memory.read_byte(0xFF02), 0x7E);
+        memory.write_byte(0xFF07, 0x05);
+        assert_eq!(memory.read_byte(0xFF07), 0xFD);
+        memory.write_byte(0xFF26, 0x80);
+        memory.write_byte(0xFF11, 0x80);
+        assert_eq!(memory.read_byte(0xFF11), 0xBF);
+        assert_eq!(memory.read_byte(0xFF13), 0xFF);
+
+        // Unmapped registers read as 0xFF
+        assert_eq!(memory.read_byte(0xFF03), 0xFF);
+        assert_eq!(memory.read_byte(0xFF27), 0xFF);
+        assert_eq!(memory.read_byte(0xFF7F), 0xFF);
+    }
+
+    #[test]
+    pub fn test_io_write_side_effects() {
+        let mut memory = Memory::new();
+
+        // DIV counts up and any write resets it
+        memory.step(0x300);
+        assert_eq!(memory.read_byte(0xFF04), 0x03);
+        memory.write_byte(0xFF04, 0x55);
+        assert_eq!(memory.read_byte(0xFF04), 0x00);
+
+        // LY and the low bits of STAT are read-only, STAT bit 7 reads as 1
+        memory.write_byte(0xFF44, 0x12);
+        assert_eq!(memory.read_byte(0xFF44), 0x00);
+        memory.write_byte(0xFF45, 0x01);
+        memory.write_byte(0xFF41, 0xFF);
+        assert_eq!(memory.read_byte(0xFF41), 0xF8);
+
+        // Sound registers cannot be written while the APU is off, and powering off clears them
+        memory.write_byte(0xFF24, 0x77);
+        assert_eq!(memory.read_byte(0xFF24), 0x00);
+        memory.write_byte(0xFF26, 0x80);
+        memory.write_byte(0xFF24, 0x77);
+        assert_eq!(memory.read_byte(0xFF24), 0x77);
+        memory.write_byte(0xFF26, 0x00);
+        assert_eq!(memory.read_byte(0xFF24), 0x00);
+        assert_eq!(memory.read_byte(0xFF26), 0x70);
+
+        // Writing the DMA register starts a transfer and reads back the source
+        memory.write_byte(0xFF46, 0xC1);
+        assert_eq!(memory.read_byte(0xFF46), 0xC1);
+    }
+
+    #[test]
+    pub fn test_joypad() {
+        let mut memory = Memory::new();
+        memory.press_button(Button::Start);
+        memory.press_button(Button::Left);
+
+        // Select the action buttons, the pressed line reads as 0
+        memory.write_byte(0xFF00, 0x10);
+        assert_eq!(memory.read_byte(0xFF00), 0xD7);
+
+        // Select the direction buttons
+        memory.write_byte(0xFF00, 0x20);
+        assert_eq!(memory.read_byte(0xFF00), 0xED);
+
+        memory.release_button(Button::Left);
+        assert_eq!(memory.read_byte(0xFF00), 0xEF);
+    }
+
+    #[test]
+    pub fn test_serial_transfer() {
+        let mut memory = Memory::new();
+        memory.write_byte(0xFF01, 0x42);
+        memory.write_byte(0xFF02, 0x81);
+
+        // 8 bits at 8192 Hz, with nothing connected 1s are shifted in
+        memory.step(4095);
+        assert_eq!(memory.read_byte(0xFF02), 0xFF);
+        memory.step(1);
+        assert_eq!(memory.read_byte(0xFF02), 0x7F);
+        assert_eq!(memory.read_byte(0xFF01), 0xFF);
+        assert_eq!(memory.serial_output(), &[0x42]);
+    }
+
+    #[test]
+    pub fn test_interrupt_flags() {
+        let mut memory = Memory::new();
+
+        // Each interrupt sets its own IF bit even while IE is clear, the upper bits read as 1
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        memory.trigger_interrupt(Interrupt::Timer);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE4);
+        memory.trigger_interrupt(Interrupt::Joypad);
+        assert_eq!(memory.read_byte(0xFF0F), 0xF4);
+        assert_eq!(memory.pending_interrupt(), None);
+
+        // Only requested and enabled interrupts are pending, highest priority first
+        memory.write_byte(0xFFFF, 0x1F);
+        memory.trigger_interrupt(Interrupt::LCDStat);
+        assert_eq!(memory.pending_interrupt(), Some(Interrupt::LCDStat));
+        memory.acknowledge_interrupt(Interrupt::LCDStat);
+        assert_eq!(memory.pending_interrupt(), Some(Interrupt::Timer));
+        memory.write_byte(0xFFFF, 0x10);
+        assert_eq!(memory.pending_interrupt(), Some(Interrupt::Joypad));
+
+        // IF can be written directly
+        memory.write_byte(0xFF0F, 0x00);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        assert_eq!(memory.pending_interrupt(), None);
+    }
+
+    #[test]
+    pub fn test_component_interrupts() {
+        let mut memory = Memory::new();
+
+        // Timer overflow at 262144 Hz, 256 increments of 16 cycles and the reload a cycle later
+        memory.write_byte(0xFF07, 0x05);
+        memory.step(256 * 16 + 4);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE4);
+
+        // Pressing a selected button
+        memory.write_byte(0xFF00, 0x20);
+        memory.press_button(Button::Down);
+        assert_eq!(memory.read_byte(0xFF0F), 0xF4);
+    }
+
+    #[test]
+    pub fn test_cgb_vram_banking() {
+        let mut memory = Memory::new();
+        memory.set_model(Model::Cgb);
+        memory.write_byte(0x8000, 0x11);
+        memory.write_byte(0xFF4F, 0x01);
+        assert_eq!(memory.read_byte(0xFF4F), 0xFF);
+        assert_eq!(memory.read_byte(0x8000), 0x00);
+        memory.write_byte(0x9FFF, 0x22);
+        memory.write_byte(0xFF4F, 0xFE);
+        assert_eq!(memory.read_byte(0xFF4F), 0xFE);
+        assert_eq!(memory.read_byte(0x8000), 0x11);
+        assert_eq!(memory.read_byte(0x9FFF), 0x00);
+
+        // VBK does not exist on the DMG
+        let mut memory = Memory::new();
+        memory.write_byte(0xFF4F, 0x01);
+        assert_eq!(memory.read_byte(0xFF4F), 0xFF);
+        memory.write_byte(0x8000, 0x33);
+        memory.write_byte(0xFF4F, 0x00);
+        assert_eq!(memory.read_byte(0x8000), 0x33);
+    }
+
+    #[test]
+    pub fn test_cgb_wram_banking() {
+        let mut memory = Memory::new();
+        memory.set_model(Model::Cgb);
+        assert_eq!(memory.read_byte(0xFF70), 0xF9);
+        for bank in 1..8 {
+            memory.write_byte(0xFF70, bank);
+            memory.write_byte(0xD000, bank * 0x10);
+        }
+
+        // Bank 0 is always at 0xC000, selecting bank 0 maps bank 1 at 0xD000
+        memory.write_byte(0xC000, 0x99);
+        memory.write_byte(0xFF70, 0x00);
+        assert_eq!(memory.read_byte(0xFF70), 0xF8);
+        assert_eq!(memory.read_byte(0xD000), 0x10);
+        memory.write_byte(0xFF70, 0x05);
+        assert_eq!(memory.read_byte(0xD000), 0x50);
+        assert_eq!(memory.read_byte(0xC000), 0x99);
+
+        // Echo RAM follows the selected bank and only the low 3 bits are used
+        assert_eq!(memory.read_byte(0xF000), 0x50);
+        memory.write_byte(0xFF70, 0xFF);
+        assert_eq!(memory.read_byte(0xF000), 0x70);
+    }
+
+    #[test]
+    pub fn test_double_speed() {
+        let mut memory = Memory::new();
+        memory.write_byte(0xFF4D, 0x01);
+        assert_eq!(memory.read_byte(0xFF4D), 0xFF);
+        assert!(!memory.stop());
+
+        let mut memory = Memory::new();
+        memory.set_model(Model::Cgb);
+        memory.set_rtc_clock(RtcClock::Cycles);
+        memory.load_rom(&build_rom(0x10, 0x02, 0x02)).unwrap();
+        assert_eq!(memory.read_byte(0xFF4D), 0x7E);
+
+        // STOP without arming KEY1 only resets the divider
+        memory.step(0x300);
+        assert!(!memory.stop());
+        assert_eq!(memory.read_byte(0xFF04), 0x00);
+
+        // Arming KEY1 and executing STOP switches speed and clears the armed bit
+        memory.write_byte(0xFF4D, 0x01);
+        assert_eq!(memory.read_byte(0xFF4D), 0x7F);
+        assert!(memory.stop());
+        assert!(memory.double_speed());
+        assert_eq!(memory.read_byte(0xFF4D), 0xFE);
+
+        // The clock crystal keeps its speed, a second takes twice as many CPU cycles
+        memory.write_byte(0x0000, 0x0A);
+        memory.step(CYCLES_PER_SECOND);
+        memory.write_byte(0x6000, 0x00);
+        memory.write_byte(0x6000, 0x01);
+        memory.write_byte(0x4000, 0x08);
+        assert_eq!(memory.read_byte(0xA000), 0);
+        memory.step(CYCLES_PER_SECOND);
+        memory.write_byte(0x6000, 0x00);
+        memory.write_byte(0x6000, 0x01);
+        assert_eq!(memory.read_byte(0xA000), 1);
+
+        // Switching back to normal speed
+        memory.write_byte(0xFF4D, 0x01);
+        assert!(memory.stop());
+        assert!(!memory.double_speed());
+        assert_eq!(memory.read_byte(0xFF4D), 0x7E);
+    }
+}
@@ -0,0 +1,807 @@
+use std::collections::VecDeque;
+
+use crate::memory::VRAM_BANK_SIZE;
+
+// PPU modes as reported in the low bits of STAT
+pub const MODE_HBLANK: u8 = 0;
+pub const MODE_VBLANK: u8 = 1;
+pub const MODE_OAM_SCAN: u8 = 2;
+pub const MODE_DRAWING: u8 = 3;
+
+// Size of the LCD in pixels
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+// Dots (4.194304 MHz clock cycles) per scanline, the same in double speed
+pub const DOTS_PER_LINE: u32 = 456;
+// Dots spent scanning OAM at the start of each visible line
+const OAM_SCAN_DOTS: u32 = 80;
+// Dots spent drawing each visible line, fixed for the scanline renderer
+const DRAWING_DOTS: u32 = 172;
+// Scanlines per frame including the 10 lines of V-Blank
+const LINES_PER_FRAME: u8 = 154;
+// Objects drawn per scanline at most
+const MAX_OBJECTS_PER_LINE: usize = 10;
+// Dots the pixel FIFO renderer stalls for to fetch an object once the background fetcher is ready
+const OBJECT_FETCH_DOTS: u8 = 6;
+
+// RGB555 colours the four DMG shades are drawn in, from white to black
+pub const DMG_COLORS: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+// Interrupt request bits returned by the PPU, in the layout of IF
+pub const REQUEST_VBLANK: u8 = 0b0000_0001;
+pub const REQUEST_STAT: u8 = 0b0000_0010;
+
+// How the PPU draws the picture, chosen when it is created
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Renderer {
+    // Draw each line in one go as mode 3 ends, mode 3 always lasts 172 dots
+    Scanline,
+    // Run the background fetcher and pixel FIFOs dot by dot. Mode 3 varies in length with scrolling, the
+    // window and objects, and register writes take effect in the middle of a line
+    PixelFifo,
+}
+
+// Object attributes as found in OAM, with the object's position in OAM
+#[derive(Clone, Copy)]
+struct Object {
+    y: u8,
+    x: u8,
+    tile: u8,
+    attributes: u8,
+    index: u8,
+}
+
+// Pixel in the object FIFO, colour 0 is transparent
+#[derive(Clone, Copy)]
+struct ObjectPixel {
+    color: u8,
+    // Attributes of the object the pixel came from, for its palette and priority
+    attributes: u8,
+    // Position of the object in OAM, which decides between objects on the CGB
+    index: u8,
+}
+
+// State of the pixel FIFO renderer while drawing a line
+struct Fifo {
+    // Background and window colour numbers with their CGB map attributes waiting to be shifted out, the
+    // palette is applied on the way out
+    bg: VecDeque<(u8, u8)>,
+    // Object pixels lined up with the background FIFO
+    obj: VecDeque<ObjectPixel>,
+    // Dots into the current background tile fetch, 6 once the tile is fetched and waits to be pushed
+    fetch_step: u8,
+    // Tile column to fetch next, counted from the left of the background scroll position or the window
+    fetch_x: u8,
+    // CGB map attributes of the tile being fetched
+    fetch_attributes: u8,
+    // VRAM offset of the row of the tile being fetched, and its two bytes of pixel data
+    fetch_address: usize,
+    fetch_low: u8,
+    fetch_high: u8,
+    // The first tile fetched on a line is thrown away
+    first_fetch: bool,
+    // Pixels still to be dropped at the start of the line for SCX fine scrolling
+    discard: u8,
+    // Next pixel to output
+    lx: u8,
+    // Set once the window has started on this line
+    window: bool,
+    // Objects found on this line during the OAM scan that have not been fetched yet, in OAM order
+    objects: Vec<Object>,
+    // Object being fetched and the dots left until it is merged into the object FIFO
+    object_fetch: Option<(Object, u8)>,
+    // Set once all 160 pixels of the line are out
+    done: bool,
+}
+
+impl Fifo {
+    fn new() -> Fifo {
+        Fifo {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            fetch_step: 0,
+            fetch_x: 0,
+            fetch_attributes: 0,
+            fetch_address: 0,
+            fetch_low: 0,
+            fetch_high: 0,
+            first_fetch: true,
+            discard: 0,
+            lx: 0,
+            window: false,
+            objects: Vec::with_capacity(MAX_OBJECTS_PER_LINE),
+            object_fetch: None,
+            done: false,
+        }
+    }
+
+    // Get ready to draw a line with the given fine scroll and objects
+    fn start(&mut self, discard: u8, objects: Vec<Object>) {
+        *self = Fifo { discard, objects, ..Fifo::new() };
+    }
+
+    // Least number of dots before the line can be finished, as at most one pixel is shifted out per dot
+    fn min_dots_left(&self) -> u32 {
+        if self.done {
+            0
+        } else {
+            SCREEN_WIDTH as u32 - self.lx as u32 + self.discard as u32
+        }
+    }
+}
+
+// Pixel processing unit and its LCD registers (0xFF40-0xFF45, 0xFF47-0xFF4B)
+pub struct Ppu {
+    // 0xFF40: LCD control
+    lcdc: u8,
+    // 0xFF41: LCD status, only the interrupt select bits 3-6 are stored, the rest is derived
+    stat: u8,
+    // 0xFF42: background scroll Y
+    scy: u8,
+    // 0xFF43: background scroll X
+    scx: u8,
+    // 0xFF44: current scanline, read-only
+    ly: u8,
+    // 0xFF45: scanline compare
+    lyc: u8,
+    // 0xFF47: background palette
+    bgp: u8,
+    // 0xFF48: object palette 0
+    obp0: u8,
+    // 0xFF49: object palette 1
+    obp1: u8,
+    // 0xFF4A: window Y position
+    wy: u8,
+    // 0xFF4B: window X position plus 7
+    wx: u8,
+    // 0xFF68: CGB background palette index, bit 7 moves it on after each write to BCPD
+    bcps: u8,
+    // 0xFF6A: CGB object palette index, bit 7 moves it on after each write to OCPD
+    ocps: u8,
+    // 0xFF6C: CGB object priority mode, bit 0 set uses the DMG rule of the lowest X winning
+    opri: u8,
+    // CGB palette RAM read and written through BCPD (0xFF69) and OCPD (0xFF6B): 8 palettes of 4 RGB555
+    // colours each, little endian
+    bg_palettes: [u8; 64],
+    obj_palettes: [u8; 64],
+    // Set when running as a CGB, with colour palettes and the map attributes in VRAM bank 1
+    cgb: bool,
+    // Set when a CGB runs a DMG game. It draws as a DMG, with BGP picking colours from background palette 0
+    // and OBP0 and OBP1 from object palettes 0 and 1
+    compat: bool,
+    // Current PPU mode
+    mode: u8,
+    // Renderer drawing the lines
+    renderer: Renderer,
+    // Pixel FIFO renderer state, unused by the scanline renderer
+    fifo: Fifo,
+    // Level of the STAT interrupt line, the OR of all enabled STAT interrupt sources
+    stat_line: bool,
+    // Set once LY has matched WY this frame, the window can only start after that
+    window_triggered: bool,
+    // Set on the first line after the LCD is switched on, which starts in mode 0 rather than with an OAM scan
+    first_line: bool,
+    // Set for the first frame after the LCD is switched on, which the LCD does not show
+    blank_frame: bool,
+    // Dots into the current scanline
+    dot: u32,
+    // Line of the window to draw next, only counts lines the window was visible on
+    window_line: u8,
+    // Finished frame as RGB555 colours, in rows of SCREEN_WIDTH
+    framebuffer: Vec<u16>,
+    // Set when a frame is finished and cleared when the frontend picks it up
+    frame_ready: bool,
+}
+
+impl Ppu {
+    pub fn new(renderer: Renderer) -> Ppu {
+        Ppu {
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            bcps: 0,
+            ocps: 0,
+            opri: 0,
+            // The CGB boot ROM leaves the background palettes white
+            bg_palettes: [0xFF; 64],
+            obj_palettes: [0; 64],
+            cgb: false,
+            compat: false,
+            mode: MODE_HBLANK,
+            renderer,
+            fifo: Fifo::new(),
+            stat_line: false,
+            window_triggered: false,
+            first_line: false,
+            blank_frame: false,
+            dot: 0,
+            window_line: 0,
+            framebuffer: vec![DMG_COLORS[0]; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+        }
+    }
+
+    // Read an LCD register, STAT bit 7 reads as 1 and its low 3 bits report the PPU state. Palette RAM
+    // cannot be read while the PPU is drawing
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => 0b1000_0000 | self.stat | ((self.ly == self.lyc) as u8) << 2 | self.mode(),
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            0xFF68 => 0b0100_0000 | self.bcps,
+            0xFF69 if self.mode() != MODE_DRAWING => self.bg_palettes[(self.bcps & 0x3F) as usize],
+            0xFF6A => 0b0100_0000 | self.ocps,
+            0xFF6B if self.mode() != MODE_DRAWING => self.obj_palettes[(self.ocps & 0x3F) as usize],
+            0xFF6C => 0b1111_1110 | self.opri,
+            _ => 0xFF,
+        }
+    }
+
+    // Write an LCD register, LY and the STAT mode and coincidence bits are read-only. Returns REQUEST_STAT if
+    // the write raised the STAT interrupt line
+    pub fn write(&mut self, addr: u16, val: u8) -> u8 {
+        let mut requests = 0;
+        match addr {
+            0xFF40 => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = val;
+                if was_enabled != self.lcd_enabled() {
+                    self.switch_lcd();
+                }
+            },
+            0xFF41 => {
+                // On the DMG the write acts as if every source but mode 2 was selected for a cycle, which
+                // requests the interrupt in H-Blank, V-Blank or while LY matches LYC
+                if !self.cgb {
+                    self.stat = 0b0101_1000;
+                    requests |= self.update_stat_line();
+                }
+                self.stat = val & 0b0111_1000;
+            },
+            0xFF42 => self.scy = val,
+            0xFF43 => self.scx = val,
+            0xFF45 => self.lyc = val,
+            0xFF47 => self.bgp = val,
+            0xFF48 => self.obp0 = val,
+            0xFF49 => self.obp1 = val,
+            0xFF4A => self.wy = val,
+            0xFF4B => self.wx = val,
+            0xFF68 => self.bcps = val & 0b1011_1111,
+            0xFF69 => {
+                let blocked = self.mode() == MODE_DRAWING;
+                write_palette(&mut self.bg_palettes, &mut self.bcps, val, blocked);
+            },
+            0xFF6A => self.ocps = val & 0b1011_1111,
+            0xFF6B => {
+                let blocked = self.mode() == MODE_DRAWING;
+                write_palette(&mut self.obj_palettes, &mut self.ocps, val, blocked);
+            },
+            0xFF6C => self.opri = val & 0b0000_0001,
+            _ => (),
+        }
+        requests | self.update_stat_line()
+    }
+
+    // Restart the PPU at the top of the frame after LCDC bit 7 changed. Switched off, LY stays at 0 in mode 0
+    // and the LCD goes blank. Switched on, the first line starts in mode 0 and the first frame is not shown
+    fn switch_lcd(&mut self) {
+        self.ly = 0;
+        self.dot = 0;
+        self.window_line = 0;
+        self.window_triggered = false;
+        self.stat_line = false;
+        self.mode = MODE_HBLANK;
+        if self.lcd_enabled() {
+            self.first_line = true;
+            self.blank_frame = true;
+        } else {
+            self.first_line = false;
+            self.framebuffer.fill(DMG_COLORS[0]);
+            self.frame_ready = true;
+        }
+    }
+
+    // Select CGB rendering, with colour palettes and BG map attributes
+    pub fn set_cgb(&mut self, cgb: bool) {
+        self.cgb = cgb;
+        self.compat = false;
+    }
+
+    // Select DMG compatibility mode on the CGB, set through KEY0 by the boot ROM when the game has no CGB support
+    pub fn set_compat(&mut self, compat: bool) {
+        self.compat = self.cgb && compat;
+    }
+
+    // True while drawing with CGB palettes and BG map attributes, rather than as a DMG
+    fn cgb_rendering(&self) -> bool {
+        self.cgb && !self.compat
+    }
+
+    // True while LCDC bit 7 has the LCD switched on
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0b1000_0000 != 0
+    }
+
+    // Current PPU mode, H-Blank while the LCD is off
+    pub fn mode(&self) -> u8 {
+        if self.lcd_enabled() {
+            self.mode
+        } else {
+            MODE_HBLANK
+        }
+    }
+
+    // Set the current PPU mode
+    pub fn set_mode(&mut self, mode: u8) {
+        self.mode = mode & 0b11;
+    }
+
+    // Current scanline
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    // Last finished frame as RGB555 colours, SCREEN_WIDTH x SCREEN_HEIGHT. Colour correction is up to the frontend
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.framebuffer
+    }
+
+    // True once for each finished frame, the frontend calls this to know when to present the framebuffer
+    pub fn take_frame(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    // Renderer drawing the lines
+    pub fn renderer(&self) -> Renderer {
+        self.renderer
+    }
+
+    // Move the dot counter on, mode changes happen in next_mode when the scheduler gets there. The pixel
+    // FIFO renderer draws as it goes
+    pub fn advance(&mut self, dots: u32, vram: &[u8]) {
+        if !self.lcd_enabled() {
+            return;
+        }
+        if self.renderer == Renderer::PixelFifo && self.mode == MODE_DRAWING {
+            for _ in 0..dots {
+                if self.fifo.done {
+                    break;
+                }
+                self.fifo_dot(vram);
+            }
+        }
+        self.dot += dots;
+    }
+
+    // Dots until the next mode change, None while the LCD is off
+    pub fn dots_until_next_mode(&self) -> Option<u32> {
+        if !self.lcd_enabled() {
+            return None;
+        }
+        let end = match self.mode {
+            MODE_OAM_SCAN => OAM_SCAN_DOTS,
+            MODE_HBLANK if self.first_line => OAM_SCAN_DOTS,
+            MODE_DRAWING if self.renderer == Renderer::PixelFifo => return Some(self.fifo.min_dots_left()),
+            MODE_DRAWING => OAM_SCAN_DOTS + DRAWING_DOTS,
+            _ => DOTS_PER_LINE,
+        };
+        Some(end.saturating_sub(self.dot))
+    }
+
+    // Move on to the next mode, drawing the scanline as drawing ends. Returns the interrupts to request
+    // as REQUEST_VBLANK and REQUEST_STAT bits
+    pub fn next_mode(&mut self, vram: &[u8], oam: &[u8]) -> u8 {
+        let mut requests = 0;
+        match self.mode {
+            MODE_OAM_SCAN => self.start_drawing(oam),
+            MODE_HBLANK if self.first_line => {
+                self.first_line = false;
+                self.start_drawing(oam);
+            },
+            MODE_DRAWING => {
+                match self.renderer {
+                    Renderer::Scanline => self.render_line(vram, oam),
+                    // The scheduler only knows a lower bound for the end of the line, keep drawing until it is out
+                    Renderer::PixelFifo if !self.fifo.done => return 0,
+                    Renderer::PixelFifo => {
+                        if self.fifo.window {
+                            self.window_line += 1;
+                        }
+                    },
+                }
+                self.mode = MODE_HBLANK;
+            },
+            _ => {
+                self.dot = 0;
+                self.ly = (self.ly + 1) % LINES_PER_FRAME;
+                if self.ly as usize == SCREEN_HEIGHT {
+                    self.mode = MODE_VBLANK;
+                    if self.blank_frame {
+                        self.framebuffer.fill(DMG_COLORS[0]);
+                        self.blank_frame = false;
+                    }
+                    self.frame_ready = true;
+                    requests |= REQUEST_VBLANK;
+                } else if self.ly == 0 || self.mode == MODE_HBLANK {
+                    if self.ly == 0 {
+                        self.window_line = 0;
+                        self.window_triggered = false;
+                    }
+                    self.mode = MODE_OAM_SCAN;
+                }
+            },
+        }
+        requests | self.update_stat_line()
+    }
+
+    // Start mode 3 once the OAM scan is over
+    fn start_drawing(&mut self, oam: &[u8]) {
+        if self.ly == self.wy {
+            self.window_triggered = true;
+        }
+        if self.renderer == Renderer::PixelFifo {
+            let objects = self.scan_oam(oam);
+            self.fifo.start(self.scx % 8, objects);
+        }
+        self.mode = MODE_DRAWING;
+    }
+
+    // Recompute the STAT interrupt line from its sources. The interrupt is only requested as the line rises,
+    // so a source becoming active while another one holds the line high requests nothing. The line stays low
+    // while the LCD is off, and the mode 0 the first line starts in does not count as H-Blank
+    fn update_stat_line(&mut self) -> u8 {
+        let line = self.lcd_enabled()
+            && ((self.stat & 0b0000_1000 != 0 && self.mode == MODE_HBLANK && !self.first_line)
+                || (self.stat & 0b0001_0000 != 0 && self.mode == MODE_VBLANK)
+                || (self.stat & 0b0010_0000 != 0 && self.mode == MODE_OAM_SCAN)
+                || (self.stat & 0b0100_0000 != 0 && self.ly == self.lyc));
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        if rising { REQUEST_STAT } else { 0 }
+    }
+
+    // Objects covering the current line, the first 10 in OAM order
+    fn scan_oam(&self, oam: &[u8]) -> Vec<Object> {
+        let height = if self.lcdc & 0b0000_0100 != 0 { 16 } else { 8 };
+        let ly = self.ly as i32;
+        oam.chunks_exact(4)
+            .enumerate()
+            .map(|(index, entry)| Object { y: entry[0], x: entry[1], tile: entry[2], attributes: entry[3], index: index as u8 })
+            .filter(|object| {
+                let top = object.y as i32 - 16;
+                ly >= top && ly < top + height
+            })
+            .take(MAX_OBJECTS_PER_LINE)
+            .collect()
+    }
+
+    // Colour numbers of the 8 pixels of an object on the current line, left to right after flipping
+    fn object_pixels(&self, vram: &[u8], object: &Object) -> [u8; 8] {
+        let height = if self.lcdc & 0b0000_0100 != 0 { 16 } else { 8 };
+        let mut row = (self.ly as i32 - (object.y as i32 - 16)) as usize & (height - 1);
+        if object.attributes & 0b0100_0000 != 0 {
+            row = height - 1 - row;
+        }
+        // 8x16 objects use an even/odd pair of tiles
+        let tile = if height == 16 { object.tile & 0xFE } else { object.tile };
+        let bank = if self.cgb_rendering() && object.attributes & 0b0000_1000 != 0 { VRAM_BANK_SIZE } else { 0 };
+        let mut colors = [0; 8];
+        for (col, color) in colors.iter_mut().enumerate() {
+            let x = if object.attributes & 0b0010_0000 != 0 { 7 - col } else { col };
+            *color = self.tile_pixel(vram, bank + tile as usize * 16, x, row);
+        }
+        colors
+    }
+
+    // True if objects are prioritised by X coordinate then OAM order as on the DMG, rather than by OAM order
+    // alone as on the CGB
+    fn dmg_object_priority(&self) -> bool {
+        !self.cgb_rendering() || self.opri & 0b0000_0001 != 0
+    }
+
+    // True if an opaque object pixel is hidden by the background pixel under it. On the CGB the map attributes
+    // can also put the background in front, and LCDC bit 0 clear puts every object in front
+    fn object_hidden(&self, object_attributes: u8, bg_color: u8, bg_attributes: u8) -> bool {
+        if bg_color == 0 {
+            return false;
+        }
+        if self.cgb_rendering() {
+            self.lcdc & 0b0000_0001 != 0 && (object_attributes | bg_attributes) & 0b1000_0000 != 0
+        } else {
+            object_attributes & 0b1000_0000 != 0
+        }
+    }
+
+    // RGB555 colour of a background or window pixel
+    fn bg_color(&self, color: u8, attributes: u8) -> u16 {
+        if self.cgb_rendering() {
+            palette_color(&self.bg_palettes, attributes & 0b111, color)
+        } else if self.compat {
+            palette_color(&self.bg_palettes, 0, palette_shade(self.bgp, color))
+        } else {
+            DMG_COLORS[palette_shade(self.bgp, color) as usize]
+        }
+    }
+
+    // RGB555 colour of an object pixel
+    fn object_color(&self, attributes: u8, color: u8) -> u16 {
+        if self.cgb_rendering() {
+            return palette_color(&self.obj_palettes, attributes & 0b111, color);
+        }
+        let (palette, shades) = if attributes & 0b0001_0000 != 0 { (1, self.obp1) } else { (0, self.obp0) };
+        if self.compat {
+            palette_color(&self.obj_palettes, palette, palette_shade(shades, color))
+        } else {
+            DMG_COLORS[palette_shade(shades, color) as usize]
+        }
+    }
+
+    // Run the pixel FIFO renderer for one dot
+    fn fifo_dot(&mut self, vram: &[u8]) {
+        if let Some((object, dots)) = self.fifo.object_fetch {
+            if dots > 1 {
+                self.fifo.object_fetch = Some((object, dots - 1));
+            } else {
+                self.fifo.object_fetch = None;
+                self.merge_object(vram, &object);
+            }
+            return;
+        }
+
+        // An object starting at the next pixel stops the pixels shifting out. Its fetch waits for the
+        // background fetcher to get through the tile it is on, then takes OBJECT_FETCH_DOTS, 6 to 11 dots in all
+        if self.lcdc & 0b0000_0010 != 0 && self.fifo.discard == 0 && !self.fifo.first_fetch {
+            let lx = self.fifo.lx as u16;
+            if let Some(index) = self.fifo.objects.iter().position(|object| object.x as u16 <= lx + 8) {
+                if self.fifo.fetch_step >= 4 && !self.fifo.bg.is_empty() {
+                    let object = self.fifo.objects.remove(index);
+                    self.fifo.object_fetch = Some((object, OBJECT_FETCH_DOTS - 1));
+                } else {
+                    self.fetch_background(vram);
+                }
+                return;
+            }
+        }
+
+        // Starting the window throws away the background pixels and restarts the fetcher on the window map
+        if !self.fifo.window
+            && self.window_triggered
+            && self.lcdc & 0b0010_0000 != 0
+            && self.fifo.discard == 0
+            && self.fifo.lx as u16 + 7 >= self.wx as u16
+        {
+            self.fifo.window = true;
+            self.fifo.bg.clear();
+            self.fifo.fetch_step = 0;
+            self.fifo.fetch_x = 0;
+        }
+
+        self.fetch_background(vram);
+        self.shift_pixel();
+    }
+
+    // Run the background fetcher for one dot: 2 dots each to read the tile number and the two bytes of the
+    // tile row, then push the 8 pixels once the FIFO is empty. Registers are read as the fetch happens
+    fn fetch_background(&mut self, vram: &[u8]) {
+        if self.fifo.first_fetch && self.fifo.fetch_step == 6 {
+            self.fifo.first_fetch = false;
+            self.fifo.fetch_step = 0;
+        }
+        match self.fifo.fetch_step {
+            1 => {
+                let (map, x, y) = if self.fifo.window {
+                    let map = if self.lcdc & 0b0100_0000 != 0 { 0x1C00 } else { 0x1800 };
+                    (map, self.fifo.fetch_x as usize & 31, self.window_line as usize)
+                } else {
+                    let map = if self.lcdc & 0b0000_1000 != 0 { 0x1C00 } else { 0x1800 };
+                    let x = (self.scx as usize / 8 + self.fifo.fetch_x as usize) & 31;
+                    (map, x, (self.ly as usize + self.scy as usize) & 0xFF)
+                };
+                let index = map + y / 8 * 32 + x;
+                let attributes = if self.cgb_rendering() { vram[VRAM_BANK_SIZE + index] } else { 0 };
+                self.fifo.fetch_attributes = attributes;
+                self.fifo.fetch_address = self.bg_tile_row(vram[index], attributes, y % 8);
+            },
+            3 => self.fifo.fetch_low = vram[self.fifo.fetch_address],
+            5 => self.fifo.fetch_high = vram[self.fifo.fetch_address + 1],
+            6 => {
+                if self.fifo.bg.is_empty() {
+                    let attributes = self.fifo.fetch_attributes;
+                    for col in 0..8 {
+                        let bit = if attributes & 0b0010_0000 != 0 { col } else { 7 - col };
+                        let color = (((self.fifo.fetch_high >> bit) & 1) << 1) | ((self.fifo.fetch_low >> bit) & 1);
+                        self.fifo.bg.push_back((color, attributes));
+                    }
+                    self.fifo.fetch_x = self.fifo.fetch_x.wrapping_add(1);
+                    self.fifo.fetch_step = 0;
+                }
+                return;
+            },
+            _ => (),
+        }
+        self.fifo.fetch_step += 1;
+    }
+
+    // Mix a fetched object into the object FIFO. Pixels of objects fetched earlier win unless transparent,
+    // or on the CGB unless the new object comes first in OAM
+    fn merge_object(&mut self, vram: &[u8], object: &Object) {
+        let colors = self.object_pixels(vram, object);
+        let oam_order = !self.dmg_object_priority();
+        // Columns left of the current pixel are already out, or off the left edge of the screen
+        let skip = (self.fifo.lx as i32 + 8 - object.x as i32).max(0) as usize;
+        for (slot, &color) in colors.iter().skip(skip).enumerate() {
+            let pixel = ObjectPixel { color, attributes: object.attributes, index: object.index };
+            match self.fifo.obj.get_mut(slot) {
+                Some(existing) if existing.color == 0 || (oam_order && color != 0 && pixel.index < existing.index) => {
+                    *existing = pixel
+                },
+                Some(_) => (),
+                None => self.fifo.obj.push_back(pixel),
+            }
+        }
+    }
+
+    // Shift one pixel out of the FIFOs to the LCD, mixing the background and object pixels with the
+    // current palettes
+    fn shift_pixel(&mut self) {
+        let Some((color, attributes)) = self.fifo.bg.pop_front() else {
+            return;
+        };
+        let object = self.fifo.obj.pop_front();
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        // LCDC bit 0 blanks the background and window on the DMG
+        let bg_enabled = self.cgb_rendering() || self.lcdc & 0b0000_0001 != 0;
+        let bg_color = if bg_enabled { color } else { 0 };
+        let rgb = match object {
+            Some(pixel)
+                if pixel.color != 0
+                    && self.lcdc & 0b0000_0010 != 0
+                    && !self.object_hidden(pixel.attributes, bg_color, attributes) =>
+            {
+                self.object_color(pixel.attributes, pixel.color)
+            },
+            _ if bg_enabled => self.bg_color(color, attributes),
+            _ => DMG_COLORS[0],
+        };
+        self.framebuffer[self.ly as usize * SCREEN_WIDTH + self.fifo.lx as usize] = rgb;
+        self.fifo.lx += 1;
+        if self.fifo.lx as usize == SCREEN_WIDTH {
+            self.fifo.done = true;
+        }
+    }
+
+    // Draw the current scanline into the framebuffer from the tile data and maps in VRAM and the objects in OAM
+    fn render_line(&mut self, vram: &[u8], oam: &[u8]) {
+        let y = self.ly as usize;
+        if y >= SCREEN_HEIGHT {
+            return;
+        }
+        // Background colour numbers before the palette and CGB map attributes, which decide whether objects show
+        let mut bg_pixels = [(0u8, 0u8); SCREEN_WIDTH];
+        let mut line = [DMG_COLORS[0]; SCREEN_WIDTH];
+
+        // LCDC bit 0 blanks the background and window on the DMG, on the CGB it only takes away their priority
+        if self.cgb_rendering() || self.lcdc & 0b0000_0001 != 0 {
+            let window_visible = self.lcdc & 0b0010_0000 != 0 && self.window_triggered && self.wx <= 166;
+            for (x, pixel) in line.iter_mut().enumerate() {
+                let (map, map_x, map_y) = if window_visible && x + 7 >= self.wx as usize {
+                    let map = if self.lcdc & 0b0100_0000 != 0 { 0x1C00 } else { 0x1800 };
+                    (map, x + 7 - self.wx as usize, self.window_line as usize)
+                } else {
+                    let map = if self.lcdc & 0b0000_1000 != 0 { 0x1C00 } else { 0x1800 };
+                    (map, (x + self.scx as usize) & 0xFF, (y + self.scy as usize) & 0xFF)
+                };
+                let index = map + map_y / 8 * 32 + map_x / 8;
+                let attributes = if self.cgb_rendering() { vram[VRAM_BANK_SIZE + index] } else { 0 };
+                let tile_x = if attributes & 0b0010_0000 != 0 { 7 - map_x % 8 } else { map_x % 8 };
+                let color = self.tile_pixel(vram, self.bg_tile_row(vram[index], attributes, map_y % 8), tile_x, 0);
+                bg_pixels[x] = (color, attributes);
+                *pixel = self.bg_color(color, attributes);
+            }
+            if window_visible && self.wx as usize <= SCREEN_WIDTH + 6 {
+                self.window_line += 1;
+            }
+        }
+
+        if self.lcdc & 0b0000_0010 != 0 {
+            self.render_objects(vram, oam, &bg_pixels, &mut line);
+        }
+        self.framebuffer[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH].copy_from_slice(&line);
+    }
+
+    // Draw the objects on the current scanline over the background. With DMG priorities the object with the
+    // lower X coordinate wins, then the one earlier in OAM. CGB priorities only go by OAM order
+    fn render_objects(&self, vram: &[u8], oam: &[u8], bg_pixels: &[(u8, u8); SCREEN_WIDTH], line: &mut [u16; SCREEN_WIDTH]) {
+        // The first 10 objects in OAM that cover this line are drawn, the rest are dropped. A stable sort
+        // keeps OAM order between objects at the same X
+        let mut objects = self.scan_oam(oam);
+        if self.dmg_object_priority() {
+            objects.sort_by_key(|object| object.x);
+        }
+
+        let mut claimed = [false; SCREEN_WIDTH];
+        for object in objects {
+            for (col, &color) in self.object_pixels(vram, &object).iter().enumerate() {
+                let x = object.x as i32 - 8 + col as i32;
+                if !(0..SCREEN_WIDTH as i32).contains(&x) || claimed[x as usize] || color == 0 {
+                    continue;
+                }
+                let x = x as usize;
+                // The first opaque object pixel hides later objects even when it is itself behind the background
+                claimed[x] = true;
+                let (bg_color, bg_attributes) = bg_pixels[x];
+                if self.object_hidden(object.attributes, bg_color, bg_attributes) {
+                    continue;
+                }
+                line[x] = self.object_color(object.attributes, color);
+            }
+        }
+    }
+
+    // VRAM offset of a background or window tile, LCDC bit 4 selects unsigned indices from 0x8000
+    // or signed ones around 0x9000
+    fn tile_address(&self, tile: u8) -> usize {
+        if self.lcdc & 0b0001_0000 != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + tile as i8 as i32 * 16) as usize
+        }
+    }
+
+    // VRAM offset of a row of a background or window tile, in the bank and with the vertical flip the CGB map
+    // attributes select
+    fn bg_tile_row(&self, tile: u8, attributes: u8, row: usize) -> usize {
+        let bank = if attributes & 0b0000_1000 != 0 { VRAM_BANK_SIZE } else { 0 };
+        let row = if attributes & 0b0100_0000 != 0 { 7 - row } else { row };
+        bank + self.tile_address(tile) + row * 2
+    }
+
+    // Colour number (0-3) of a pixel in a tile, rows beyond 7 run on into the next tile
+    fn tile_pixel(&self, vram: &[u8], address: usize, x: usize, y: usize) -> u8 {
+        let low = vram[address + y * 2];
+        let high = vram[address + y * 2 + 1];
+        let bit = 7 - x;
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+}
+
+// Shade a colour number maps to in a DMG palette register
+fn palette_shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0b11
+}
+
+// RGB555 colour of a colour number in one of the 8 palettes in CGB palette RAM
+fn palette_color(palettes: &[u8; 64], palette: u8, color: u8) -> u16 {
+    let index = palette as usize * 8 + color as usize * 2;
+    u16::from_le_bytes([palettes[index], palettes[index + 1]]) & 0x7FFF
+}
+
+// Write palette RAM at the byte the index register selects, then move the index on if its bit 7 is set.
+// Writes while the PPU is drawing are lost but still move the index on
+fn write_palette(palettes: &mut [u8; 64], index: &mut u8, val: u8, blocked: bool) {
+    if !blocked {
+        palettes[(*index & 0x3F) as usize] = val;
+    }
+    if *index & 0b1000_0000 != 0 {
+        *index = 0b1000_0000 | ((*index + 1) & 0x3F);
+    }
+}
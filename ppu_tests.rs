@@ -0,0 +1,632 @@
+use crate::boot::{BootRom, Model};
+use crate::cartridge_tests::build_rom;
+use crate::memory::Memory;
+use crate::ppu::{Renderer, DMG_COLORS, DOTS_PER_LINE, MODE_DRAWING, MODE_HBLANK, MODE_OAM_SCAN, MODE_VBLANK, SCREEN_WIDTH};
+
+#[cfg(test)]
+pub mod tests {
+    use super::*; // Import the functions and types from the parent module
+
+    // Clock cycles in a frame of 154 scanlines
+    const FRAME_CYCLES: u32 = DOTS_PER_LINE * 154;
+
+    // Memory with the LCD off, tile 1 filled with colour 3, tile 2 with colour 1 and the identity palette
+    // in BGP, OBP0 and OBP1
+    fn setup(renderer: Renderer) -> Memory {
+        let mut memory = Memory::with_renderer(renderer);
+        for i in 0..16 {
+            memory.write_byte(0x8010 + i, 0xFF);
+            memory.write_byte(0x8020 + i, if i % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        memory.write_byte(0xFF47, 0xE4);
+        memory.write_byte(0xFF48, 0xE4);
+        memory.write_byte(0xFF49, 0xE4);
+        memory
+    }
+
+    // Run the two frames after switching the LCD on, the first one is not shown
+    fn run_frames(memory: &mut Memory) {
+        memory.step(2 * FRAME_CYCLES);
+    }
+
+    // Run until LY reaches the given line
+    fn run_to_line(memory: &mut Memory, ly: u8) {
+        while memory.read_byte(0xFF44) != ly {
+            memory.step(4);
+        }
+    }
+
+    // One rendered line of the framebuffer as DMG shades
+    fn line(memory: &Memory, y: usize) -> Vec<u8> {
+        rgb_line(memory, y).iter().map(|&rgb| DMG_COLORS.iter().position(|&c| c == rgb).unwrap() as u8).collect()
+    }
+
+    // One rendered line of the framebuffer as RGB555 colours
+    fn rgb_line(memory: &Memory, y: usize) -> &[u16] {
+        &memory.ppu().framebuffer()[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH]
+    }
+
+    #[test]
+    pub fn test_ppu_mode_timing() {
+        let mut memory = setup(Renderer::Scanline);
+        memory.write_byte(0xFF40, 0x91);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_HBLANK);
+
+        // The first line after switching the LCD on has mode 0 in place of the OAM scan. After that lines
+        // have 80 dots of OAM scan, 172 of drawing, then H-Blank to the end of the 456 dot line
+        memory.step(79);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_HBLANK);
+        memory.step(1);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_DRAWING);
+        memory.step(172);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_HBLANK);
+        memory.step(204);
+        assert_eq!(memory.read_byte(0xFF44), 1);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_OAM_SCAN);
+        memory.step(79);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_OAM_SCAN);
+        memory.step(1);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_DRAWING);
+        memory.step(DOTS_PER_LINE - 80);
+
+        // V-Blank starts at line 144 and requests its interrupt
+        memory.step(DOTS_PER_LINE * 142);
+        assert_eq!(memory.read_byte(0xFF44), 144);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_VBLANK);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE1);
+        assert!(memory.ppu_mut().take_frame());
+        assert!(!memory.ppu_mut().take_frame());
+
+        // After 10 lines of V-Blank the next frame starts
+        memory.step(DOTS_PER_LINE * 9);
+        assert_eq!(memory.read_byte(0xFF44), 153);
+        memory.step(DOTS_PER_LINE);
+        assert_eq!(memory.read_byte(0xFF44), 0);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_OAM_SCAN);
+
+        // Switching the LCD off stops the PPU at line 0 in mode 0
+        memory.step(DOTS_PER_LINE * 3);
+        memory.write_byte(0xFF40, 0x11);
+        assert_eq!(memory.read_byte(0xFF44), 0);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_HBLANK);
+        assert_eq!(memory.cycles_until_next_event(), None);
+    }
+
+    #[test]
+    pub fn test_ppu_stat_interrupts() {
+        let mut memory = setup(Renderer::Scanline);
+
+        // H-Blank source
+        memory.write_byte(0xFF41, 0b0000_1000);
+        memory.write_byte(0xFF40, 0x91);
+        memory.step(251);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        memory.step(1);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE2);
+
+        // LY=LYC source, and the coincidence flag in STAT
+        memory.write_byte(0xFF0F, 0x00);
+        memory.write_byte(0xFF41, 0b0100_0000);
+        memory.write_byte(0xFF45, 3);
+        memory.step(DOTS_PER_LINE * 2 + 204 - 1);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        memory.step(1);
+        assert_eq!(memory.read_byte(0xFF44), 3);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE2);
+        assert_eq!(memory.read_byte(0xFF41) & 0b0000_0100, 0b0000_0100);
+    }
+
+    #[test]
+    pub fn test_ppu_background() {
+        // Both renderers draw the same picture
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut memory = setup(renderer);
+            // Tile 1 at the top left of the map at 0x9800, tile 2 at the second row of tiles
+            memory.write_byte(0x9800, 1);
+            memory.write_byte(0x9820, 2);
+            memory.write_byte(0xFF43, 4);
+            memory.write_byte(0xFF40, 0x91);
+            run_frames(&mut memory);
+
+            // SCX scrolls the first tile halfway off the screen
+            assert_eq!(&line(&memory, 0)[..5], &[3, 3, 3, 3, 0]);
+            assert_eq!(&line(&memory, 8)[..5], &[1, 1, 1, 1, 0]);
+            assert_eq!(line(&memory, 16)[0], 0);
+
+            // Signed tile indices from 0x9000 with LCDC bit 4 clear, the palette maps the colours
+            memory.write_byte(0xFF40, 0x00);
+            for i in 0..16 {
+                memory.write_byte(0x9010 + i, 0xFF);
+            }
+            memory.write_byte(0xFF47, 0b0001_1011);
+            memory.write_byte(0xFF40, 0x81);
+            run_frames(&mut memory);
+            assert_eq!(&line(&memory, 0)[..5], &[0, 0, 0, 0, 3]);
+
+            // LCDC bit 0 blanks the background to white
+            memory.write_byte(0xFF40, 0x80);
+            run_frames(&mut memory);
+            assert_eq!(&line(&memory, 0)[..5], &[0, 0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    pub fn test_ppu_window() {
+        // Both renderers draw the same picture
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut memory = setup(renderer);
+            // The window uses the map at 0x9C00, from WX-7 = 80 and WY = 2
+            memory.write_byte(0x9C00, 1);
+            memory.write_byte(0xFF4A, 2);
+            memory.write_byte(0xFF4B, 87);
+            memory.write_byte(0xFF40, 0xF1);
+            run_frames(&mut memory);
+            assert_eq!(line(&memory, 1)[80], 0);
+            assert_eq!(&line(&memory, 2)[79..90], &[0, 3, 3, 3, 3, 3, 3, 3, 3, 0, 0]);
+            // The window's own line counter starts at its first visible line
+            assert_eq!(line(&memory, 9)[80], 3);
+            assert_eq!(line(&memory, 10)[80], 0);
+        }
+    }
+
+    #[test]
+    pub fn test_window_wy_latch() {
+        // Both renderers draw the same picture
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut memory = setup(renderer);
+            // Every window line shows colour 3 from x 80, the background is colour 0
+            for row in 0..32 {
+                memory.write_byte(0x9C00 + row * 32, 1);
+            }
+            memory.write_byte(0xFF4A, 2);
+            memory.write_byte(0xFF4B, 87);
+            memory.write_byte(0xFF40, 0xF1);
+            run_frames(&mut memory);
+
+            // Once LY has matched WY the window stays on for the rest of the frame, even if WY moves past LY
+            run_to_line(&mut memory, 40);
+            memory.write_byte(0xFF4A, 100);
+            run_to_line(&mut memory, 144);
+            assert_eq!(line(&memory, 60)[80], 3);
+
+            // WY moved below LY without ever matching it does not start the window
+            memory.write_byte(0xFF4A, 200);
+            run_to_line(&mut memory, 50);
+            memory.write_byte(0xFF4A, 10);
+            run_to_line(&mut memory, 144);
+            assert_eq!(line(&memory, 60)[80], 0);
+        }
+    }
+
+    #[test]
+    pub fn test_ppu_sprites() {
+        // Both renderers draw the same picture
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut memory = setup(renderer);
+            // Object 0 at screen (10, 0) with tile 2, object 1 at (6, 0) with tile 1 and OBP1
+            memory.write_byte(0xFE00, 16);
+            memory.write_byte(0xFE01, 18);
+            memory.write_byte(0xFE02, 2);
+            memory.write_byte(0xFE04, 16);
+            memory.write_byte(0xFE05, 14);
+            memory.write_byte(0xFE06, 1);
+            memory.write_byte(0xFE07, 0b0001_0000);
+            memory.write_byte(0xFF49, 0b1001_0000);
+            memory.write_byte(0xFF40, 0x93);
+            run_frames(&mut memory);
+
+            // The object with the lower X wins where they overlap
+            assert_eq!(&line(&memory, 0)[5..19], &[0, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 0]);
+
+            // Objects behind the background only show over colour 0, and still hide objects behind them
+            memory.write_byte(0xFF40, 0x00);
+            memory.write_byte(0xFE07, 0b1001_0000);
+            memory.write_byte(0x9800, 1);
+            memory.write_byte(0xFF40, 0x93);
+            run_frames(&mut memory);
+            assert_eq!(&line(&memory, 0)[5..19], &[3, 3, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 0]);
+
+            // Only the first 10 objects on a line are drawn
+            memory.write_byte(0xFF40, 0x00);
+            for i in 0..12 {
+                memory.write_byte(0xFE00 + i * 4, 16 + 20);
+                memory.write_byte(0xFE01 + i * 4, 8 + i as u8 * 8);
+                memory.write_byte(0xFE02 + i * 4, 1);
+                memory.write_byte(0xFE03 + i * 4, 0);
+            }
+            memory.write_byte(0xFF40, 0x93);
+            run_frames(&mut memory);
+            assert_eq!(line(&memory, 20)[79], 3);
+            assert_eq!(line(&memory, 20)[80], 0);
+
+            // 8x16 objects use the even tile of the pair on top, Y flip swaps them
+            memory.write_byte(0xFF40, 0x00);
+            for i in 0..0xA0 {
+                memory.write_byte(0xFE00 + i, 0);
+            }
+            memory.write_byte(0x9800, 0);
+            memory.write_byte(0xFE00, 16);
+            memory.write_byte(0xFE01, 8);
+            memory.write_byte(0xFE02, 3);
+            memory.write_byte(0xFE03, 0b0100_0000);
+            memory.write_byte(0xFF40, 0x97);
+            run_frames(&mut memory);
+            assert_eq!(line(&memory, 0)[0], 0);
+            assert_eq!(line(&memory, 8)[0], 1);
+        }
+    }
+
+    // Dots mode 3 of the first line lasts after switching the LCD on with the given LCDC value
+    fn drawing_dots(memory: &mut Memory, lcdc: u8) -> u32 {
+        memory.write_byte(0xFF40, lcdc);
+        memory.step(80);
+        let mut dots = 0;
+        while memory.read_byte(0xFF41) & 0b11 == MODE_DRAWING {
+            memory.step(1);
+            dots += 1;
+        }
+        memory.write_byte(0xFF40, 0x00);
+        dots
+    }
+
+    #[test]
+    pub fn test_fifo_drawing_length() {
+        let mut memory = setup(Renderer::PixelFifo);
+        assert_eq!(drawing_dots(&mut memory, 0x93), 172);
+
+        // Fine scrolling drops SCX % 8 pixels at the start of the line
+        memory.write_byte(0xFF43, 13);
+        assert_eq!(drawing_dots(&mut memory, 0x93), 177);
+        memory.write_byte(0xFF43, 0);
+
+        // Starting the window restarts the fetcher
+        memory.write_byte(0xFF4B, 87);
+        assert_eq!(drawing_dots(&mut memory, 0xB3), 178);
+
+        // An object waits for the background fetcher, 11 dots at the start of a tile and 6 near its end
+        memory.write_byte(0xFE00, 16);
+        memory.write_byte(0xFE01, 8 + 40);
+        assert_eq!(drawing_dots(&mut memory, 0x93), 183);
+        memory.write_byte(0xFE01, 8 + 45);
+        assert_eq!(drawing_dots(&mut memory, 0x93), 178);
+
+        // A second object at the same place only pays for its own fetch
+        memory.write_byte(0xFE01, 8 + 40);
+        memory.write_byte(0xFE04, 16);
+        memory.write_byte(0xFE05, 8 + 40);
+        assert_eq!(drawing_dots(&mut memory, 0x93), 189);
+
+        // Objects cost nothing while they are disabled, and the scanline renderer never varies
+        assert_eq!(drawing_dots(&mut memory, 0x91), 172);
+        let mut memory = setup(Renderer::Scanline);
+        memory.write_byte(0xFE00, 16);
+        memory.write_byte(0xFE01, 8 + 40);
+        assert_eq!(drawing_dots(&mut memory, 0x93), 172);
+    }
+
+    #[test]
+    pub fn test_fifo_mid_line_writes() {
+        let mut memory = setup(Renderer::PixelFifo);
+        for i in 0..32 {
+            memory.write_byte(0x9800 + i, 1);
+        }
+        memory.write_byte(0xFF40, 0x91);
+
+        // The first pixel comes out 12 dots into mode 3, one pixel per dot after that. A palette write
+        // after 50 pixels changes the rest of the line
+        memory.step(80 + 12 + 50);
+        memory.write_byte(0xFF47, 0x00);
+        memory.step(DOTS_PER_LINE - (80 + 12 + 50));
+        assert_eq!(&line(&memory, 0)[48..52], &[3, 3, 0, 0]);
+
+        // SCX is read as each tile number is fetched. Tile columns 5 and 9 are blank, moving SCX on by a tile
+        // after 64 pixels skips column 9 from the next fetch on while column 5 stays where it was
+        memory.write_byte(0xFF47, 0xE4);
+        memory.write_byte(0x9800 + 5, 0);
+        memory.write_byte(0x9800 + 9, 0);
+        memory.step(80 + 12 + 64);
+        memory.write_byte(0xFF43, 8);
+        memory.step(DOTS_PER_LINE - (80 + 12 + 64));
+        let line1 = line(&memory, 1);
+        assert_eq!(&line1[32..40], &[3; 8]);
+        assert_eq!(&line1[40..48], &[0; 8]);
+        assert_eq!(&line1[64..80], &[3; 16]);
+    }
+
+    #[test]
+    pub fn test_stat_interrupt_blocking() {
+        let mut memory = setup(Renderer::Scanline);
+        memory.write_byte(0xFF41, 0b0100_1000);
+        memory.write_byte(0xFF45, 1);
+        memory.write_byte(0xFF40, 0x91);
+
+        // LY=LYC raises the STAT line at the start of line 1
+        memory.step(DOTS_PER_LINE);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE2);
+
+        // H-Blank on the same line finds the line already high and requests nothing
+        memory.write_byte(0xFF0F, 0x00);
+        memory.step(252);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_HBLANK);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+
+        // On line 2 the line drops in mode 2, so H-Blank raises it again
+        memory.step(DOTS_PER_LINE);
+        assert_eq!(memory.read_byte(0xFF44), 2);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE2);
+    }
+
+    // CGB memory with colour c of background palette p set to p * 4 + c + 1 and of object palette p to
+    // 0x100 + p * 4 + c. Tile 1 is filled with colour 3 in bank 0 and colour 1 in bank 1, tile 3 in bank 0
+    // only has its top left pixel set to colour 3
+    fn setup_cgb(renderer: Renderer) -> Memory {
+        let mut memory = Memory::with_renderer(renderer);
+        memory.set_model(Model::Cgb);
+        memory.write_byte(0xFF68, 0x80);
+        memory.write_byte(0xFF6A, 0x80);
+        for i in 0..32u16 {
+            for byte in (i + 1).to_le_bytes() {
+                memory.write_byte(0xFF69, byte);
+            }
+            for byte in (0x100 + i).to_le_bytes() {
+                memory.write_byte(0xFF6B, byte);
+            }
+        }
+        for i in 0..16 {
+            memory.write_byte(0x8010 + i, 0xFF);
+        }
+        memory.write_byte(0x8030, 0x80);
+        memory.write_byte(0x8031, 0x80);
+        memory.write_byte(0xFF4F, 1);
+        for i in 0..16 {
+            memory.write_byte(0x8010 + i, if i % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        memory.write_byte(0xFF4F, 0);
+        memory
+    }
+
+    #[test]
+    pub fn test_cgb_palette_ram() {
+        let mut memory = setup_cgb(Renderer::Scanline);
+
+        // Auto-increment wraps around the 64 bytes, bit 6 of the index registers reads as 1
+        memory.write_byte(0xFF68, 0xBE);
+        memory.write_byte(0xFF69, 0x12);
+        memory.write_byte(0xFF69, 0x34);
+        assert_eq!(memory.read_byte(0xFF68), 0xC0);
+        assert_eq!(memory.read_byte(0xFF69), 0x01);
+
+        // Without auto-increment the index stays put, reads never move it
+        memory.write_byte(0xFF68, 0x3E);
+        assert_eq!(memory.read_byte(0xFF69), 0x12);
+        assert_eq!(memory.read_byte(0xFF69), 0x12);
+        assert_eq!(memory.read_byte(0xFF68), 0x7E);
+        assert_eq!(memory.read_byte(0xFF6C), 0xFE);
+
+        // Palette RAM is out of reach while drawing, writes are lost but still move the index on
+        memory.write_byte(0xFF40, 0x80);
+        memory.step(80);
+        memory.write_byte(0xFF6A, 0x80);
+        memory.write_byte(0xFF6B, 0x55);
+        assert_eq!(memory.read_byte(0xFF6A), 0xC1);
+        assert_eq!(memory.read_byte(0xFF6B), 0xFF);
+        memory.step(172);
+        memory.write_byte(0xFF6A, 0x00);
+        assert_eq!(memory.read_byte(0xFF6B), 0x00);
+
+        // The registers do not exist on the DMG
+        let mut memory = setup(Renderer::Scanline);
+        assert_eq!(memory.read_byte(0xFF68), 0xFF);
+        assert_eq!(memory.read_byte(0xFF6B), 0xFF);
+    }
+
+    #[test]
+    pub fn test_cgb_background_attributes() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut memory = setup_cgb(renderer);
+            // Palette 2, bank 1 with palette 5, horizontal flip and vertical flip
+            memory.write_byte(0x9800, 1);
+            memory.write_byte(0x9801, 1);
+            memory.write_byte(0x9802, 3);
+            memory.write_byte(0x9803, 3);
+            memory.write_byte(0xFF4F, 1);
+            memory.write_byte(0x9800, 0b0000_0010);
+            memory.write_byte(0x9801, 0b0000_1101);
+            memory.write_byte(0x9802, 0b0010_0000);
+            memory.write_byte(0x9803, 0b0100_0000);
+            memory.write_byte(0xFF4F, 0);
+            memory.write_byte(0xFF40, 0x91);
+            run_frames(&mut memory);
+
+            let line0 = rgb_line(&memory, 0);
+            assert_eq!(&line0[..8], &[2 * 4 + 3 + 1; 8]);
+            assert_eq!(&line0[8..16], &[5 * 4 + 1 + 1; 8]);
+            assert_eq!(&line0[16..24], &[1, 1, 1, 1, 1, 1, 1, 4]);
+            assert_eq!(&line0[24..32], &[1; 8]);
+            assert_eq!(&rgb_line(&memory, 7)[24..26], &[4, 1]);
+
+            // LCDC bit 0 does not blank the background on the CGB
+            memory.write_byte(0xFF40, 0x90);
+            run_frames(&mut memory);
+            assert_eq!(rgb_line(&memory, 0)[0], 2 * 4 + 3 + 1);
+        }
+    }
+
+    #[test]
+    pub fn test_cgb_compat_mode() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            // A DMG game started on a CGB without a boot ROM draws as on the DMG
+            let mut memory = setup(renderer);
+            memory.set_model(Model::Cgb);
+            memory.load_rom(&build_rom(0x00, 0x00, 0x00)).unwrap();
+            memory.skip_boot();
+            memory.write_byte(0xFF40, 0x00);
+            memory.write_byte(0xFF47, 0xE4);
+            memory.write_byte(0x9800, 1);
+            // Map attributes are ignored, these would select palette 1 and the empty tile 1 in bank 1
+            memory.write_byte(0xFF4F, 1);
+            memory.write_byte(0x9800, 0b0000_1001);
+            memory.write_byte(0xFF4F, 0);
+            memory.write_byte(0xFF40, 0x91);
+            run_frames(&mut memory);
+            assert_eq!(&line(&memory, 0)[6..10], &[3, 3, 0, 0]);
+
+            // LCDC bit 0 blanks the background as on the DMG
+            memory.write_byte(0xFF40, 0x90);
+            run_frames(&mut memory);
+            assert_eq!(line(&memory, 0)[6], 0);
+
+            // With a boot ROM, the boot ROM selects the mode through KEY0 and sets the colours
+            let mut memory = setup(renderer);
+            memory.set_model(Model::Cgb);
+            memory.load_rom(&build_rom(0x00, 0x00, 0x00)).unwrap();
+            memory.load_boot_rom(BootRom::from_bytes(&[0; 0x900]).unwrap());
+            memory.write_byte(0xFF4C, 0x04);
+            // Colour 3 of background palette 0 is red, colour 3 of object palette 1 is green
+            memory.write_byte(0xFF68, 6);
+            memory.write_byte(0xFF69, 0x1F);
+            memory.write_byte(0xFF68, 7);
+            memory.write_byte(0xFF69, 0x00);
+            memory.write_byte(0xFF6A, 14);
+            memory.write_byte(0xFF6B, 0xE0);
+            memory.write_byte(0xFF6A, 15);
+            memory.write_byte(0xFF6B, 0x03);
+            memory.write_byte(0xFF50, 0x01);
+            // Only the boot ROM can write KEY0
+            memory.write_byte(0xFF4C, 0x00);
+
+            // BGP maps through background palette 0, OBP1 through object palette 1
+            memory.write_byte(0x9800, 1);
+            memory.write_byte(0xFE00, 16);
+            memory.write_byte(0xFE01, 8 + 20);
+            memory.write_byte(0xFE02, 1);
+            memory.write_byte(0xFE03, 0b0001_0000);
+            memory.write_byte(0xFF40, 0x93);
+            run_frames(&mut memory);
+            assert_eq!(&rgb_line(&memory, 0)[6..10], &[0x001F, 0x001F, 0x7FFF, 0x7FFF]);
+            assert_eq!(rgb_line(&memory, 0)[20], 0x03E0);
+        }
+    }
+
+    #[test]
+    pub fn test_cgb_object_priority() {
+        for renderer in [Renderer::Scanline, Renderer::PixelFifo] {
+            let mut memory = setup_cgb(renderer);
+            // Object 0 at screen X 4 with palette 1, object 1 at screen X 0 with tile 1 from bank 1 and palette 2
+            memory.write_byte(0xFE00, 16);
+            memory.write_byte(0xFE01, 12);
+            memory.write_byte(0xFE02, 1);
+            memory.write_byte(0xFE03, 0b0000_0001);
+            memory.write_byte(0xFE04, 16);
+            memory.write_byte(0xFE05, 8);
+            memory.write_byte(0xFE06, 1);
+            memory.write_byte(0xFE07, 0b0000_1010);
+            memory.write_byte(0xFF40, 0x93);
+            run_frames(&mut memory);
+
+            // The object first in OAM wins on the CGB
+            let line0 = rgb_line(&memory, 0);
+            assert_eq!(&line0[..4], &[0x100 + 2 * 4 + 1; 4]);
+            assert_eq!(&line0[4..12], &[0x100 + 4 + 3; 8]);
+
+            // OPRI bit 0 selects the DMG rule of the lowest X winning
+            memory.write_byte(0xFF40, 0x00);
+            memory.write_byte(0xFF6C, 0x01);
+            memory.write_byte(0xFF40, 0x93);
+            run_frames(&mut memory);
+            let line0 = rgb_line(&memory, 0);
+            assert_eq!(&line0[..8], &[0x100 + 2 * 4 + 1; 8]);
+            assert_eq!(&line0[8..12], &[0x100 + 4 + 3; 4]);
+
+            // The priority bit in the map attributes puts the background over objects
+            memory.write_byte(0xFF40, 0x00);
+            memory.write_byte(0x9801, 1);
+            memory.write_byte(0xFF4F, 1);
+            memory.write_byte(0x9801, 0b1000_0000);
+            memory.write_byte(0xFF4F, 0);
+            memory.write_byte(0xFF40, 0x93);
+            run_frames(&mut memory);
+            assert_eq!(&rgb_line(&memory, 0)[7..10], &[0x100 + 2 * 4 + 1, 4, 4]);
+
+            // LCDC bit 0 clear puts all objects in front of the background
+            memory.write_byte(0xFF40, 0x92);
+            run_frames(&mut memory);
+            assert_eq!(&rgb_line(&memory, 0)[7..10], &[0x100 + 2 * 4 + 1, 0x100 + 4 + 3, 0x100 + 4 + 3]);
+        }
+    }
+
+    #[test]
+    pub fn test_lcd_switching() {
+        let mut memory = setup(Renderer::Scanline);
+        memory.write_byte(0xFF47, 0xFF);
+
+        // The first frame after switching the LCD on is blank, the next one is drawn
+        memory.write_byte(0xFF40, 0x91);
+        memory.step(FRAME_CYCLES);
+        assert!(memory.ppu_mut().take_frame());
+        assert_eq!(line(&memory, 0)[0], 0);
+        memory.step(FRAME_CYCLES);
+        assert!(memory.ppu_mut().take_frame());
+        assert_eq!(line(&memory, 0)[0], 3);
+
+        // Switching it off in the middle of a frame resets LY to 0 in mode 0 and blanks the LCD
+        memory.step(DOTS_PER_LINE * 50 + 100);
+        assert_eq!(memory.read_byte(0xFF44), 50);
+        memory.write_byte(0xFF40, 0x11);
+        assert_eq!(memory.read_byte(0xFF44), 0);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_HBLANK);
+        assert!(memory.ppu_mut().take_frame());
+        assert_eq!(line(&memory, 0)[0], 0);
+
+        // While it is off the STAT line stays low, even with LY matching LYC
+        memory.write_byte(0xFF0F, 0x00);
+        memory.write_byte(0xFF41, 0b0100_1000);
+        memory.step(FRAME_CYCLES);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        assert_eq!(memory.read_byte(0xFF44), 0);
+
+        // Switching it on with LY matching LYC raises the line straight away
+        memory.write_byte(0xFF40, 0x91);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE2);
+    }
+
+    #[test]
+    pub fn test_stat_register_writes() {
+        let mut memory = setup(Renderer::Scanline);
+        memory.write_byte(0xFF45, 5);
+        memory.write_byte(0xFF40, 0x91);
+        memory.step(DOTS_PER_LINE * 2 + 100);
+
+        // Moving LYC onto LY with its source selected raises the line
+        memory.write_byte(0xFF41, 0b0100_0000);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        memory.write_byte(0xFF45, 2);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE2);
+
+        // Selecting another active source while the line is high requests nothing
+        memory.write_byte(0xFF0F, 0x00);
+        memory.step(200);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_HBLANK);
+        memory.write_byte(0xFF41, 0b0100_1000);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+
+        // On the DMG a STAT write in H-Blank requests the interrupt if the line was low, even selecting no source
+        memory.write_byte(0xFF45, 0);
+        memory.write_byte(0xFF41, 0x00);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        memory.step(DOTS_PER_LINE - 300 + 100);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, MODE_DRAWING);
+        memory.write_byte(0xFF41, 0x00);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        memory.step(200);
+        memory.write_byte(0xFF41, 0x00);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE2);
+
+        // The CGB does not have this bug
+        let mut memory = setup_cgb(Renderer::Scanline);
+        memory.write_byte(0xFF40, 0x91);
+        memory.step(DOTS_PER_LINE + 300);
+        memory.write_byte(0xFF41, 0x00);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+    }
+}
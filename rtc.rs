@@ -0,0 +1,286 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Clock cycles per second at the normal 4.194304 MHz clock
+pub const CYCLES_PER_SECOND: u32 = 4_194_304;
+// Size of the RTC footer appended to save files, with a 64-bit timestamp
+pub const RTC_FOOTER_SIZE: usize = 48;
+// Size of the older footer variant with a 32-bit timestamp
+pub const RTC_FOOTER_SIZE_LEGACY: usize = 44;
+
+// Time source driving the real-time clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtcClock {
+    // Follow the host's wall clock, the game keeps time while the emulator is closed
+    WallClock,
+    // Count emulated clock cycles, deterministic and follows fast-forward and pauses
+    Cycles,
+}
+
+// The five MBC3 clock registers in their hardware layout
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RtcRegisters {
+    // 0x08: seconds, 0-59
+    pub seconds: u8,
+    // 0x09: minutes, 0-59
+    pub minutes: u8,
+    // 0x0A: hours, 0-23
+    pub hours: u8,
+    // 0x0B: lower 8 bits of the day counter
+    pub days_low: u8,
+    // 0x0C: bit 0 day counter bit 8, bit 6 halt, bit 7 day counter carry
+    pub days_high: u8,
+}
+
+impl RtcRegisters {
+    // Read a register selected by its RAM bank number (0x08-0x0C), unused bits read as 0
+    pub fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0x08 => self.seconds & 0b0011_1111,
+            0x09 => self.minutes & 0b0011_1111,
+            0x0A => self.hours & 0b0001_1111,
+            0x0B => self.days_low,
+            0x0C => self.days_high & 0b1100_0001,
+            _ => 0xFF,
+        }
+    }
+
+    // Day counter, 0-511
+    pub fn days(&self) -> u16 {
+        ((self.days_high as u16 & 0x01) << 8) | self.days_low as u16
+    }
+
+    // True when the clock is stopped
+    pub fn halted(&self) -> bool {
+        self.days_high & 0b0100_0000 != 0
+    }
+
+    // Set the day counter, setting the carry bit when it overflows past 511
+    fn set_days(&mut self, days: u64) {
+        if days > 0x1FF {
+            self.days_high |= 0b1000_0000;
+        }
+        let days = (days & 0x1FF) as u16;
+        self.days_low = days as u8;
+        self.days_high = (self.days_high & 0b1111_1110) | (days >> 8) as u8;
+    }
+
+    // True when every counter holds a value the clock can reach by counting
+    fn in_range(&self) -> bool {
+        self.seconds < 60 && self.minutes < 60 && self.hours < 24
+    }
+
+    // Advance the clock by whole seconds, a halted clock does not move
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.halted() {
+            return;
+        }
+        // Count single seconds while a register is out of range so the overflow quirks apply
+        while seconds > 0 && !self.in_range() {
+            self.tick_second();
+            seconds -= 1;
+        }
+        if seconds == 0 {
+            return;
+        }
+        let total = self.seconds as u64 + self.minutes as u64 * 60 + self.hours as u64 * 3600 + self.days() as u64 * 86400 + seconds;
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.set_days(total / 86400);
+    }
+
+    // Append the registers as five little-endian 32-bit values, the layout used in save file footers
+    fn write_footer(&self, out: &mut Vec<u8>) {
+        for reg in [self.seconds, self.minutes, self.hours, self.days_low, self.days_high] {
+            out.extend_from_slice(&(reg as u32).to_le_bytes());
+        }
+    }
+
+    // Parse five little-endian 32-bit values as written by write_footer
+    fn read_footer(data: &[u8]) -> RtcRegisters {
+        let reg = |i: usize| data[i * 4];
+        RtcRegisters { seconds: reg(0) & 0b0011_1111, minutes: reg(1) & 0b0011_1111, hours: reg(2) & 0b0001_1111, days_low: reg(3), days_high: reg(4) & 0b1100_0001 }
+    }
+
+    // Advance the clock by one second. Out of range values count up to their bit width and wrap to 0
+    // without carrying into the next register, as the hardware does
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0b0011_1111;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0b0011_1111;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0b0001_1111;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.set_days(self.days() as u64 + 1);
+    }
+}
+
+// MBC3 real-time clock
+pub struct Rtc {
+    // Running clock registers
+    regs: RtcRegisters,
+    // Copy of the registers taken by the last latch, this is what the game reads
+    latched: RtcRegisters,
+    // Set by writing 0x00 to the latch register, a following 0x01 latches the clock
+    latch_ready: bool,
+    // Time source driving the clock
+    clock: RtcClock,
+    // Cycles counted towards the next second in Cycles mode
+    cycles: u32,
+    // Wall clock time the registers were last brought up to date, in WallClock mode
+    last_sync: Duration,
+}
+
+impl Rtc {
+    pub fn new(clock: RtcClock) -> Rtc {
+        Rtc {
+            regs: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            latch_ready: false,
+            clock,
+            cycles: 0,
+            last_sync: now(),
+        }
+    }
+
+    // Running clock registers, bringing them up to date with the wall clock first
+    pub fn registers(&mut self) -> RtcRegisters {
+        self.sync();
+        self.regs
+    }
+
+    // Registers as captured by the last latch
+    pub fn latched(&self) -> RtcRegisters {
+        self.latched
+    }
+
+    // Time source driving the clock
+    pub fn clock(&self) -> RtcClock {
+        self.clock
+    }
+
+    // Change the time source, time passed so far under the old source is kept
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.sync();
+        self.clock = clock;
+        self.last_sync = now();
+    }
+
+    // Advance the clock by the given number of emulated clock cycles, only used in Cycles mode
+    pub fn tick(&mut self, cycles: u32) {
+        if self.clock != RtcClock::Cycles || self.regs.halted() {
+            return;
+        }
+        self.cycles += cycles;
+        if self.cycles >= CYCLES_PER_SECOND {
+            let seconds = self.cycles / CYCLES_PER_SECOND;
+            self.cycles %= CYCLES_PER_SECOND;
+            self.advance(seconds as u64);
+        }
+    }
+
+    // Clock cycles until the clock counts its next second, None when it is halted or not counting cycles
+    pub fn cycles_until_tick(&self) -> Option<u32> {
+        if self.clock != RtcClock::Cycles || self.regs.halted() {
+            return None;
+        }
+        Some(CYCLES_PER_SECOND - self.cycles)
+    }
+
+    // Advance the clock by whole seconds, a halted clock does not move
+    pub fn advance(&mut self, seconds: u64) {
+        self.regs.advance(seconds);
+    }
+
+    // Save file footer holding the running and latched registers and the current UNIX time,
+    // in the 48-byte layout shared by most emulators
+    pub fn footer(&self) -> Vec<u8> {
+        let mut regs = self.regs;
+        if self.clock == RtcClock::WallClock {
+            regs.advance(now().saturating_sub(self.last_sync).as_secs());
+        }
+        let mut out = Vec::with_capacity(RTC_FOOTER_SIZE);
+        regs.write_footer(&mut out);
+        self.latched.write_footer(&mut out);
+        out.extend_from_slice(&now().as_secs().to_le_bytes());
+        out
+    }
+
+    // Restore the clock from a save file footer and advance it by the wall clock time passed since
+    // the save was written. Both the 48-byte layout and the older 44-byte one with a 32-bit timestamp
+    // are accepted, returns false for anything else
+    pub fn load_footer(&mut self, data: &[u8]) -> bool {
+        let timestamp = match data.len() {
+            RTC_FOOTER_SIZE => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            RTC_FOOTER_SIZE_LEGACY => u32::from_le_bytes(data[40..44].try_into().unwrap()) as u64,
+            _ => return false,
+        };
+        self.regs = RtcRegisters::read_footer(&data[0..20]);
+        self.latched = RtcRegisters::read_footer(&data[20..40]);
+        self.regs.advance(now().as_secs().saturating_sub(timestamp));
+        self.cycles = 0;
+        self.last_sync = now();
+        true
+    }
+
+    // Handle a write to the latch register (0x6000-0x7FFF), writing 0x00 then 0x01 latches the clock
+    pub fn write_latch(&mut self, val: u8) {
+        if self.latch_ready && val == 0x01 {
+            self.sync();
+            self.latched = self.regs;
+        }
+        self.latch_ready = val == 0x00;
+    }
+
+    // Read a latched register selected by its RAM bank number (0x08-0x0C)
+    pub fn read(&self, reg: u8) -> u8 {
+        self.latched.read(reg)
+    }
+
+    // Write a running register selected by its RAM bank number (0x08-0x0C)
+    pub fn write(&mut self, reg: u8, val: u8) {
+        self.sync();
+        match reg {
+            0x08 => {
+                self.regs.seconds = val & 0b0011_1111;
+                // Writing the seconds resets the divider counting towards the next second
+                self.cycles = 0;
+                self.last_sync = now();
+            },
+            0x09 => self.regs.minutes = val & 0b0011_1111,
+            0x0A => self.regs.hours = val & 0b0001_1111,
+            0x0B => self.regs.days_low = val,
+            0x0C => self.regs.days_high = val & 0b1100_0001,
+            _ => (),
+        }
+    }
+
+    // Apply wall clock time passed since the last sync
+    fn sync(&mut self) {
+        if self.clock != RtcClock::WallClock {
+            return;
+        }
+        let now = now();
+        let elapsed = now.saturating_sub(self.last_sync).as_secs();
+        if self.regs.halted() {
+            self.last_sync = now;
+            return;
+        }
+        self.last_sync += Duration::from_secs(elapsed);
+        self.regs.advance(elapsed);
+    }
+}
+
+// Current wall clock time since the UNIX epoch
+fn now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
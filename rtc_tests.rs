@@ -0,0 +1,116 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::rtc::{Rtc, RtcClock, CYCLES_PER_SECOND, RTC_FOOTER_SIZE};
+
+#[cfg(test)]
+pub mod tests {
+    use super::*; // Import the functions and types from the parent module
+
+    // Latch the clock and return the latched registers
+    fn latch(rtc: &mut Rtc) -> [u8; 5] {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        [rtc.read(0x08), rtc.read(0x09), rtc.read(0x0A), rtc.read(0x0B), rtc.read(0x0C)]
+    }
+
+    #[test]
+    pub fn test_rtc_counts_cycles() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+
+        rtc.tick(CYCLES_PER_SECOND - 1);
+        assert_eq!(latch(&mut rtc), [0, 0, 0, 0, 0]);
+        rtc.tick(1);
+        assert_eq!(latch(&mut rtc), [1, 0, 0, 0, 0]);
+
+        // 1 day, 1 hour, 1 minute and 1 second later
+        rtc.advance(86400 + 3600 + 60);
+        assert_eq!(latch(&mut rtc), [1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    pub fn test_rtc_latch() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+        rtc.advance(5);
+        latch(&mut rtc);
+
+        // Reads return the latched value until the next 0x00, 0x01 sequence
+        rtc.advance(5);
+        assert_eq!(rtc.read(0x08), 5);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 5);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 10);
+    }
+
+    #[test]
+    pub fn test_rtc_day_carry_and_halt() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+
+        // Day 511, 23:59:59 overflows to day 0 and sets the carry bit
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+        rtc.write(0x0A, 23);
+        rtc.write(0x09, 59);
+        rtc.write(0x08, 59);
+        rtc.advance(1);
+        assert_eq!(latch(&mut rtc), [0, 0, 0, 0, 0x80]);
+
+        // The carry bit stays set until it is cleared by a write
+        rtc.advance(86400);
+        assert_eq!(latch(&mut rtc), [0, 0, 0, 1, 0x80]);
+        rtc.write(0x0C, 0x00);
+        assert_eq!(latch(&mut rtc)[4], 0x00);
+
+        // A halted clock does not count
+        rtc.write(0x0C, 0x40);
+        rtc.tick(CYCLES_PER_SECOND * 3);
+        assert_eq!(latch(&mut rtc), [0, 0, 0, 1, 0x40]);
+    }
+
+    #[test]
+    pub fn test_rtc_out_of_range_values() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+
+        // Seconds written past 59 count up to 63 and wrap to 0 without incrementing the minutes
+        rtc.write(0x08, 62);
+        rtc.advance(1);
+        assert_eq!(latch(&mut rtc)[0..2], [63, 0]);
+        rtc.advance(1);
+        assert_eq!(latch(&mut rtc)[0..2], [0, 0]);
+        rtc.advance(60);
+        assert_eq!(latch(&mut rtc)[0..2], [0, 1]);
+    }
+
+    #[test]
+    pub fn test_rtc_footer() {
+        let mut rtc = Rtc::new(RtcClock::Cycles);
+        rtc.write(0x0B, 0x34);
+        rtc.write(0x0A, 5);
+        latch(&mut rtc);
+        rtc.write(0x09, 10);
+
+        // Running registers, latched registers and a 64-bit timestamp, all little-endian
+        let mut footer = rtc.footer();
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE);
+        assert_eq!(footer[0..20], [0, 0, 0, 0, 10, 0, 0, 0, 5, 0, 0, 0, 0x34, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(footer[20..40], [0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 0x34, 0, 0, 0, 0, 0, 0, 0]);
+
+        // Pretend the save was written two hours and three seconds ago
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        footer[40..48].copy_from_slice(&(now - 7203).to_le_bytes());
+        let mut loaded = Rtc::new(RtcClock::Cycles);
+        assert!(loaded.load_footer(&footer));
+        assert_eq!(loaded.registers().hours, 7);
+        assert_eq!(loaded.registers().minutes, 10);
+        assert_eq!(loaded.registers().seconds, 3);
+        assert_eq!(loaded.latched(), rtc.latched());
+
+        // The older layout with a 32-bit timestamp is accepted too
+        let mut legacy = footer[0..40].to_vec();
+        legacy.extend_from_slice(&(now as u32).to_le_bytes());
+        assert!(loaded.load_footer(&legacy));
+        assert_eq!(loaded.registers().hours, 5);
+        assert!(!loaded.load_footer(&footer[0..40]));
+    }
+}
@@ -0,0 +1,76 @@
+// Hardware events that can be scheduled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    // TMA is reloaded into TIMA after an overflow, requesting the timer interrupt
+    TimerOverflow,
+    // A serial transfer shifts its last bit
+    SerialTransfer,
+    // OAM DMA runs its next M-cycle
+    OamDma,
+    // The PPU moves on to its next mode
+    PpuMode,
+    // The APU frame sequencer moves on to its next step, 512 times a second
+    ApuFrameSequencer,
+    // The cartridge real-time clock counts its next second
+    RtcTick,
+}
+
+// Keeps the time of the next event of each kind so the system can skip straight to it
+pub struct Scheduler {
+    // CPU clock cycles since power on
+    now: u64,
+    // Scheduled events as (time, event), sorted by time. Each event is scheduled at most once
+    events: Vec<(u64, Event)>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler { now: 0, events: Vec::new() }
+    }
+
+    // Current time in CPU clock cycles
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    // Schedule an event the given number of cycles from now, replacing the event if it was already scheduled
+    pub fn schedule(&mut self, event: Event, cycles: u64) {
+        self.cancel(event);
+        let time = self.now + cycles;
+        // Events due at the same time fire in the order they were scheduled
+        let index = self.events.partition_point(|&(t, _)| t <= time);
+        self.events.insert(index, (time, event));
+    }
+
+    // Remove an event if it is scheduled
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|&(_, e)| e != event);
+    }
+
+    // True if the event is scheduled
+    pub fn is_scheduled(&self, event: Event) -> bool {
+        self.events.iter().any(|&(_, e)| e == event)
+    }
+
+    // Cycles from now until the next event, None when nothing is scheduled
+    pub fn cycles_until_next(&self) -> Option<u64> {
+        self.events.first().map(|&(time, _)| time.saturating_sub(self.now))
+    }
+
+    // Remove the earliest event due at or before the given time and move the clock to it
+    pub fn pop_due(&mut self, until: u64) -> Option<Event> {
+        match self.events.first() {
+            Some(&(time, event)) if time <= until => {
+                self.events.remove(0);
+                self.now = self.now.max(time);
+                Some(event)
+            },
+            _ => None,
+        }
+    }
+
+    // Move the clock forward to the given time, events due before it must have been popped
+    pub fn advance_to(&mut self, time: u64) {
+        self.now = self.now.max(time);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::apu::FRAME_SEQUENCER_PERIOD;
+use crate::cartridge_tests::build_rom;
+use crate::cpu::Cpu;
+use crate::memory::Memory;
+use crate::rtc::{RtcClock, CYCLES_PER_SECOND};
+use crate::scheduler::{Event, Scheduler};
+
+#[cfg(test)]
+pub mod tests {
+    use super::*; // Import the functions and types from the parent module
+
+    #[test]
+    pub fn test_scheduler_order() {
+        let mut scheduler = Scheduler::new();
+        scheduler.schedule(Event::OamDma, 20);
+        scheduler.schedule(Event::TimerOverflow, 10);
+        scheduler.schedule(Event::SerialTransfer, 20);
+        assert_eq!(scheduler.cycles_until_next(), Some(10));
+
+        // Nothing is due yet
+        assert_eq!(scheduler.pop_due(9), None);
+
+        // Events come out in time order, ties in the order they were scheduled
+        assert_eq!(scheduler.pop_due(30), Some(Event::TimerOverflow));
+        assert_eq!(scheduler.now(), 10);
+        assert_eq!(scheduler.cycles_until_next(), Some(10));
+        assert_eq!(scheduler.pop_due(30), Some(Event::OamDma));
+        assert_eq!(scheduler.pop_due(30), Some(Event::SerialTransfer));
+        assert_eq!(scheduler.pop_due(30), None);
+        scheduler.advance_to(30);
+        assert_eq!(scheduler.now(), 30);
+        assert_eq!(scheduler.cycles_until_next(), None);
+    }
+
+    #[test]
+    pub fn test_scheduler_reschedule() {
+        let mut scheduler = Scheduler::new();
+
+        // Scheduling an event again moves it rather than adding a second one
+        scheduler.schedule(Event::TimerOverflow, 100);
+        scheduler.schedule(Event::TimerOverflow, 50);
+        assert_eq!(scheduler.cycles_until_next(), Some(50));
+        assert_eq!(scheduler.pop_due(200), Some(Event::TimerOverflow));
+        assert_eq!(scheduler.pop_due(200), None);
+
+        scheduler.schedule(Event::SerialTransfer, 10);
+        assert!(scheduler.is_scheduled(Event::SerialTransfer));
+        scheduler.cancel(Event::SerialTransfer);
+        assert!(!scheduler.is_scheduled(Event::SerialTransfer));
+        assert_eq!(scheduler.pop_due(200), None);
+    }
+
+    #[test]
+    pub fn test_register_writes_reschedule() {
+        let mut memory = Memory::new();
+        assert_eq!(memory.cycles_until_next_event(), None);
+
+        // Starting the timer schedules its interrupt, 256 increments of 16 cycles and the reload delay away
+        memory.write_byte(0xFF07, 0x05);
+        assert_eq!(memory.cycles_until_next_event(), Some(256 * 16 + 4));
+
+        // Writing TIMA moves it
+        memory.write_byte(0xFF05, 0xFE);
+        assert_eq!(memory.cycles_until_next_event(), Some(2 * 16 + 4));
+
+        // The interrupt is requested at the exact cycle inside a longer step
+        memory.step(2 * 16 + 3);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE0);
+        memory.step(1);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE4);
+
+        // Stopping the timer cancels it
+        memory.write_byte(0xFF07, 0x00);
+        assert_eq!(memory.cycles_until_next_event(), None);
+    }
+
+    #[test]
+    pub fn test_halted_cpu_skips_to_next_event() {
+        let mut memory = Memory::new();
+        memory.write_byte(0xC000, 0x76);
+        memory.write_byte(0xFFFF, 0x04);
+        memory.write_byte(0xFF07, 0x04);
+        memory.write_byte(0xFF05, 0xFF);
+        let mut cpu = Cpu::new();
+        cpu.pc = 0xC000;
+        cpu.step(&mut memory);
+        assert!(cpu.halted);
+
+        // The overflow at 4096 Hz is 1024 clock cycles after the timer started and the interrupt follows
+        // with the reload an M-cycle later, HALT took the first M-cycle
+        assert_eq!(cpu.step(&mut memory), 256);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE4);
+        cpu.step(&mut memory);
+        assert!(!cpu.halted);
+    }
+
+    #[test]
+    pub fn test_frame_sequencer_and_clock_events() {
+        let mut memory = Memory::new();
+
+        // Powering the APU on starts the frame sequencer
+        memory.write_byte(0xFF26, 0x80);
+        assert_eq!(memory.cycles_until_next_event(), Some(FRAME_SEQUENCER_PERIOD as u64));
+
+        // A channel with one step of length left is switched off by the first length clock
+        memory.write_byte(0xFF11, 0x3F);
+        memory.write_byte(0xFF12, 0xF0);
+        memory.write_byte(0xFF14, 0xC0);
+        assert_eq!(memory.read_byte(0xFF26), 0xF1);
+        memory.step(FRAME_SEQUENCER_PERIOD - 1);
+        assert_eq!(memory.read_byte(0xFF26), 0xF1);
+        memory.step(1);
+        assert_eq!(memory.read_byte(0xFF26), 0xF0);
+
+        // Powering it off stops the frame sequencer
+        memory.write_byte(0xFF26, 0x00);
+        assert_eq!(memory.cycles_until_next_event(), None);
+
+        // A cartridge clock counting cycles schedules its next second, halting it cancels that
+        let mut memory = Memory::new();
+        memory.set_rtc_clock(RtcClock::Cycles);
+        memory.load_rom(&build_rom(0x10, 0x02, 0x03)).unwrap();
+        assert_eq!(memory.cycles_until_next_event(), Some(CYCLES_PER_SECOND as u64));
+        memory.step(CYCLES_PER_SECOND / 2);
+        assert_eq!(memory.cycles_until_next_event(), Some(CYCLES_PER_SECOND as u64 / 2));
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0x4000, 0x0C);
+        memory.write_byte(0xA000, 0x40);
+        assert_eq!(memory.cycles_until_next_event(), None);
+
+        // Restarting it keeps the half second already counted
+        memory.write_byte(0xA000, 0x00);
+        assert_eq!(memory.cycles_until_next_event(), Some(CYCLES_PER_SECOND as u64 / 2));
+        memory.step(CYCLES_PER_SECOND / 2);
+        memory.write_byte(0x6000, 0x00);
+        memory.write_byte(0x6000, 0x01);
+        memory.write_byte(0x4000, 0x08);
+        assert_eq!(memory.read_byte(0xA000), 1);
+    }
+}
@@ -0,0 +1,77 @@
+// Clock cycles per bit with the internal 8192 Hz serial clock
+const CYCLES_PER_BIT: u32 = 512;
+
+// Serial port (0xFF01-0xFF02)
+pub struct Serial {
+    // 0xFF01: serial transfer data
+    sb: u8,
+    // 0xFF02: serial transfer control, bit 7 starts a transfer and bit 0 selects the internal clock
+    sc: u8,
+    // Bits left to shift in the current transfer
+    bits_left: u8,
+    // Cycles counted towards the next bit
+    cycles: u32,
+    // Every byte sent, handy for test ROMs that report their results over the link port
+    output: Vec<u8>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial { sb: 0, sc: 0, bits_left: 0, cycles: 0, output: Vec::new() }
+    }
+
+    // Read a serial register, unused SC bits read as 1
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 => 0b0111_1110 | self.sc,
+            _ => 0xFF,
+        }
+    }
+
+    // Write a serial register, setting bits 7 and 0 of SC starts a transfer on the internal clock
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF01 => self.sb = val,
+            0xFF02 => {
+                self.sc = val & 0b1000_0001;
+                if self.sc == 0b1000_0001 {
+                    self.output.push(self.sb);
+                    self.bits_left = 8;
+                    self.cycles = 0;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    // Bytes sent over the link port so far
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    // Advance the serial clock by the given number of clock cycles. With no link partner connected
+    // every bit shifted in is 1
+    pub fn tick(&mut self, cycles: u32) {
+        if self.bits_left == 0 {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_BIT && self.bits_left > 0 {
+            self.cycles -= CYCLES_PER_BIT;
+            self.sb = (self.sb << 1) | 1;
+            self.bits_left -= 1;
+        }
+        if self.bits_left == 0 {
+            self.sc &= 0b0111_1111;
+        }
+    }
+
+    // Clock cycles until the running transfer completes, None when no transfer is running
+    pub fn cycles_until_done(&self) -> Option<u32> {
+        if self.bits_left == 0 {
+            return None;
+        }
+        Some(self.bits_left as u32 * CYCLES_PER_BIT - self.cycles)
+    }
+}
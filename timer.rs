@@ -0,0 +1,173 @@
+// Clock cycles between TIMA overflowing and TMA being loaded into it, TIMA reads 0 in between
+const RELOAD_DELAY: u32 = 4;
+
+// Timer and divider (0xFF04-0xFF07)
+pub struct Timer {
+    // Internal 16-bit divider counting clock cycles, DIV is its upper byte
+    counter: u16,
+    // 0xFF05: timer counter
+    tima: u8,
+    // 0xFF06: timer modulo, loaded into TIMA when it overflows
+    tma: u8,
+    // 0xFF07: timer control, bit 2 enables the timer and bits 0-1 select the frequency
+    tac: u8,
+    // Cycles left until TMA is loaded after an overflow, 0 when no reload is pending
+    reload_delay: u32,
+    // Cycles since TMA was last loaded into TIMA, writes in the M-cycle of the reload behave differently
+    since_reload: u32,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer { counter: 0, tima: 0, tma: 0, tac: 0, reload_delay: 0, since_reload: u32::MAX }
+    }
+
+    // Read a timer register, unused TAC bits read as 1
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => 0b1111_1000 | self.tac,
+            _ => 0xFF,
+        }
+    }
+
+    // Write a timer register. TIMA counts falling edges of the enabled, selected divider bit, so resetting
+    // the divider or changing TAC can make that signal fall and increment TIMA
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF04 => {
+                let signal = self.signal();
+                self.counter = 0;
+                if signal {
+                    self.increment();
+                }
+            },
+            0xFF05 => {
+                if self.reload_delay > 0 {
+                    // Writing TIMA between the overflow and the reload cancels the reload and the interrupt
+                    self.reload_delay = 0;
+                    self.tima = val;
+                } else if !self.reloading() {
+                    // In the M-cycle of the reload TMA wins over the written value
+                    self.tima = val;
+                }
+            },
+            0xFF06 => {
+                self.tma = val;
+                // In the M-cycle of the reload the new TMA value goes straight through to TIMA
+                if self.reloading() {
+                    self.tima = val;
+                }
+            },
+            0xFF07 => {
+                let signal = self.signal();
+                self.tac = val & 0b0000_0111;
+                if signal && !self.signal() {
+                    self.increment();
+                }
+            },
+            _ => (),
+        }
+    }
+
+    // Set DIV directly, used to restore the state the boot ROM leaves behind
+    pub fn set_div(&mut self, div: u8) {
+        self.counter = (div as u16) << 8;
+    }
+
+    // Advance the timer by the given number of clock cycles
+    pub fn tick(&mut self, cycles: u32) {
+        let mut remaining = cycles;
+        while remaining > 0 {
+            if self.reload_delay > 0 {
+                let step = remaining.min(self.reload_delay);
+                self.advance_counter(step);
+                remaining -= step;
+                self.reload_delay -= step;
+                if self.reload_delay == 0 {
+                    self.tima = self.tma;
+                    self.since_reload = 0;
+                }
+                continue;
+            }
+            if !self.enabled() {
+                self.advance_counter(remaining);
+                return;
+            }
+            // Count whole edges arithmetically up to the next overflow
+            let period = self.period();
+            let phase = self.counter as u32 & (period - 1);
+            let to_overflow = (period - phase) + (0xFF - self.tima as u32) * period;
+            if remaining < to_overflow {
+                self.tima += ((phase + remaining) / period) as u8;
+                self.advance_counter(remaining);
+                return;
+            }
+            self.advance_counter(to_overflow);
+            remaining -= to_overflow;
+            self.tima = 0;
+            self.reload_delay = RELOAD_DELAY;
+        }
+    }
+
+    // Clock cycles until the timer next requests its interrupt, which happens when TMA is reloaded.
+    // None while the timer is stopped and no reload is pending
+    pub fn cycles_until_interrupt(&self) -> Option<u32> {
+        if self.reload_delay > 0 {
+            return Some(self.reload_delay);
+        }
+        if !self.enabled() {
+            return None;
+        }
+        let period = self.period();
+        let next_edge = period - (self.counter as u32 & (period - 1));
+        Some(next_edge + (0xFF - self.tima as u32) * period + RELOAD_DELAY)
+    }
+
+    // True while the timer is enabled in TAC
+    fn enabled(&self) -> bool {
+        self.tac & 0b100 != 0
+    }
+
+    // Input to the falling edge detector: the selected divider bit ANDed with the enable bit
+    fn signal(&self) -> bool {
+        self.enabled() && self.counter & self.selected_bit() != 0
+    }
+
+    // True in the M-cycle TMA is loaded into TIMA
+    fn reloading(&self) -> bool {
+        self.since_reload < RELOAD_DELAY
+    }
+
+    // Increment TIMA once, an overflow leaves it at 0 until the reload
+    fn increment(&mut self) {
+        if self.tima == 0xFF {
+            self.tima = 0;
+            self.reload_delay = RELOAD_DELAY;
+        } else {
+            self.tima += 1;
+        }
+    }
+
+    fn advance_counter(&mut self, cycles: u32) {
+        self.counter = self.counter.wrapping_add(cycles as u16);
+        self.since_reload = self.since_reload.saturating_add(cycles);
+    }
+
+    // Clock cycles between two falling edges of the selected divider bit
+    fn period(&self) -> u32 {
+        self.selected_bit() as u32 * 2
+    }
+
+    // Divider bit whose falling edge increments TIMA at the frequency selected in TAC
+    fn selected_bit(&self) -> u16 {
+        match self.tac & 0b11 {
+            0b00 => 1 << 9, // 4096 Hz
+            0b01 => 1 << 3, // 262144 Hz
+            0b10 => 1 << 5, // 65536 Hz
+            _ => 1 << 7,    // 16384 Hz
+        }
+    }
+}
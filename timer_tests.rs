@@ -0,0 +1,121 @@
+use crate::timer::Timer;
+
+#[cfg(test)]
+pub mod tests {
+    use super::*; // Import the functions and types from the parent module
+
+    // Timer counting at 262144 Hz, one increment every 16 cycles on the falling edge of divider bit 3
+    fn fast_timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.write(0xFF07, 0x05);
+        timer
+    }
+
+    #[test]
+    pub fn test_timer_counts_falling_edges() {
+        let mut timer = fast_timer();
+        timer.tick(15);
+        assert_eq!(timer.read(0xFF05), 0);
+        timer.tick(1);
+        assert_eq!(timer.read(0xFF05), 1);
+        timer.tick(16 * 10);
+        assert_eq!(timer.read(0xFF05), 11);
+        assert_eq!(timer.read(0xFF04), 0);
+        timer.tick(256 - 176);
+        assert_eq!(timer.read(0xFF04), 1);
+
+        // Disabled, only DIV counts
+        timer.write(0xFF07, 0x01);
+        timer.tick(0x1000);
+        assert_eq!(timer.read(0xFF05), 16);
+        assert_eq!(timer.read(0xFF04), 17);
+        assert_eq!(timer.cycles_until_interrupt(), None);
+    }
+
+    #[test]
+    pub fn test_timer_div_write_glitch() {
+        // Resetting the divider while the selected bit is set is a falling edge
+        let mut timer = fast_timer();
+        timer.tick(8);
+        timer.write(0xFF04, 0x12);
+        assert_eq!((timer.read(0xFF04), timer.read(0xFF05)), (0, 1));
+
+        // With the bit clear nothing happens, and the next edge is a full period away
+        timer.tick(4);
+        timer.write(0xFF04, 0x00);
+        assert_eq!(timer.read(0xFF05), 1);
+        timer.tick(15);
+        assert_eq!(timer.read(0xFF05), 1);
+        timer.tick(1);
+        assert_eq!(timer.read(0xFF05), 2);
+    }
+
+    #[test]
+    pub fn test_timer_tac_glitch() {
+        // Disabling the timer while the selected bit is set is a falling edge
+        let mut timer = fast_timer();
+        timer.tick(8);
+        timer.write(0xFF07, 0x01);
+        assert_eq!(timer.read(0xFF05), 1);
+
+        // So is switching to a frequency whose bit is clear
+        timer.write(0xFF07, 0x05);
+        timer.write(0xFF07, 0x06);
+        assert_eq!(timer.read(0xFF05), 2);
+
+        // Switching between two set bits is not
+        let mut timer = fast_timer();
+        timer.tick(0x28);
+        timer.write(0xFF07, 0x06);
+        assert_eq!(timer.read(0xFF05), 2);
+    }
+
+    #[test]
+    pub fn test_timer_overflow_reload() {
+        let mut timer = fast_timer();
+        timer.write(0xFF06, 0x80);
+        timer.write(0xFF05, 0xFE);
+        assert_eq!(timer.cycles_until_interrupt(), Some(2 * 16 + 4));
+
+        // TIMA reads 0 for one M-cycle before TMA is loaded and the interrupt requested
+        timer.tick(32);
+        assert_eq!(timer.read(0xFF05), 0x00);
+        assert_eq!(timer.cycles_until_interrupt(), Some(4));
+        timer.tick(4);
+        assert_eq!(timer.read(0xFF05), 0x80);
+        assert_eq!(timer.cycles_until_interrupt(), Some(128 * 16 - 4 + 4));
+
+        // Several overflows in one go keep counting from TMA
+        timer.tick(128 * 16 * 2);
+        assert_eq!(timer.read(0xFF05), 0x80);
+    }
+
+    #[test]
+    pub fn test_timer_writes_during_reload() {
+        // Writing TIMA between the overflow and the reload cancels the reload and the interrupt
+        let mut timer = fast_timer();
+        timer.write(0xFF05, 0xFF);
+        timer.tick(16);
+        timer.write(0xFF05, 0x42);
+        timer.tick(4);
+        assert_eq!(timer.read(0xFF05), 0x42);
+        assert_eq!(timer.cycles_until_interrupt(), Some(12 + (0xFF - 0x42) * 16 + 4));
+
+        // In the M-cycle of the reload TIMA writes are ignored and TMA writes go through to TIMA
+        let mut timer = fast_timer();
+        timer.write(0xFF06, 0x10);
+        timer.write(0xFF05, 0xFF);
+        timer.tick(20);
+        timer.write(0xFF05, 0x42);
+        assert_eq!(timer.read(0xFF05), 0x10);
+        timer.write(0xFF06, 0x20);
+        assert_eq!(timer.read(0xFF05), 0x20);
+
+        // One M-cycle later writes behave normally again
+        timer.tick(4);
+        timer.write(0xFF06, 0x30);
+        assert_eq!(timer.read(0xFF05), 0x20);
+        timer.write(0xFF05, 0x42);
+        assert_eq!(timer.read(0xFF05), 0x42);
+    }
+}